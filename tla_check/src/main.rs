@@ -0,0 +1,311 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use tla_instrumentation::checker::{
+    check_declarations, check_tla_code_link, CheckerConfig, PredicateDescription,
+};
+use tla_instrumentation::read_trace_file;
+
+/// Checks the state pairs of recorded update traces against a transition predicate of a
+/// TLA+ spec
+#[derive(Debug, Parser)]
+#[command(name = "tla-check", version)]
+struct Args {
+    /// The trace files, as written by `write_trace_file`
+    #[arg(required = true)]
+    traces: Vec<PathBuf>,
+    /// The TLA+ module that defines the transition predicate
+    #[arg(long)]
+    spec: PathBuf,
+    /// The transition predicate, e.g., `Next`
+    #[arg(long)]
+    action: String,
+    /// A parameter of the transition predicate, as a TLA+ expression. Can be repeated.
+    #[arg(long = "param")]
+    params: Vec<String>,
+    /// Only check the traces of this update. Can be repeated.
+    #[arg(long = "update")]
+    updates: Vec<String>,
+    /// Only check the pair with this (0-based) index in each trace. Can be repeated.
+    #[arg(long = "pair")]
+    pairs: Vec<usize>,
+    #[arg(long, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    /// The checker configuration file. Defaults to the `TLA_CHECK_*` environment variables.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// The Apalache executable, overriding the configuration
+    #[arg(long)]
+    apalache: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+/// The outcome of checking one recorded state pair
+#[derive(Debug)]
+struct PairResult {
+    file: PathBuf,
+    update_name: String,
+    process_id: String,
+    pair_index: usize,
+    /// The error message, if the check failed
+    error: Option<String>,
+    bundle: Option<PathBuf>,
+    /// Constants that the spec declares, but the trace didn't record
+    unrecorded_constants: Vec<String>,
+}
+
+impl Args {
+    fn checker_config(&self) -> Result<CheckerConfig, String> {
+        let mut config = match &self.config {
+            Some(path) => CheckerConfig::from_file(path)?,
+            None => CheckerConfig::from_env()?,
+        };
+        if let Some(binary) = &self.apalache {
+            config.binary = binary.clone();
+        }
+        Ok(config)
+    }
+
+    fn predicate(&self) -> PredicateDescription {
+        PredicateDescription {
+            tla_module: self.spec.clone(),
+            transition_predicate: self.action.clone(),
+            predicate_parameters: self.params.iter().map(|p| p.as_str().into()).collect(),
+        }
+    }
+}
+
+/// Checks the selected pairs, in the order of the files, traces and pairs
+fn run(args: &Args) -> Result<Vec<PairResult>, String> {
+    let config = args.checker_config()?;
+    let updates: BTreeSet<&String> = args.updates.iter().collect();
+    let pairs: BTreeSet<usize> = args.pairs.iter().copied().collect();
+    let mut results = Vec::new();
+    for file in &args.traces {
+        for trace in read_trace_file(file)? {
+            if !updates.is_empty() && !updates.contains(&trace.update_name) {
+                continue;
+            }
+            // Reading the spec can only fail here if it fails for the checks as well
+            let unrecorded_constants = check_declarations(
+                &args.spec,
+                trace.state_pairs.iter().flat_map(|p| [&p.start, &p.end]),
+                &trace.constants,
+            )
+            .map(|mismatch| mismatch.missing_constants)
+            .unwrap_or_default();
+            for (pair_index, pair) in trace.state_pairs.iter().enumerate() {
+                if !pairs.is_empty() && !pairs.contains(&pair_index) {
+                    continue;
+                }
+                let outcome = check_tla_code_link(
+                    &config,
+                    args.predicate(),
+                    pair.clone(),
+                    trace.constants.clone(),
+                );
+                results.push(PairResult {
+                    file: file.clone(),
+                    update_name: trace.update_name.clone(),
+                    process_id: trace.process_id.clone(),
+                    pair_index,
+                    error: outcome.as_ref().err().map(|e| e.apalache_error.to_string()),
+                    bundle: outcome.err().and_then(|e| e.bundle()),
+                    unrecorded_constants: unrecorded_constants.clone(),
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+fn report(format: Format, results: &[PairResult], out: &mut dyn Write) -> io::Result<()> {
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    match format {
+        Format::Human => {
+            for result in results {
+                let status = if result.error.is_some() { "FAIL" } else { "ok" };
+                writeln!(
+                    out,
+                    "{:<4} {} {} ({}) pair {}",
+                    status,
+                    result.file.display(),
+                    result.update_name,
+                    result.process_id,
+                    result.pair_index
+                )?;
+                if let Some(error) = &result.error {
+                    for line in error.lines() {
+                        writeln!(out, "     {}", line)?;
+                    }
+                }
+                if let Some(bundle) = &result.bundle {
+                    writeln!(out, "     Reproduction bundle: {}", bundle.display())?;
+                }
+                if !result.unrecorded_constants.is_empty() {
+                    writeln!(
+                        out,
+                        "     Warning: constants {} weren't recorded; the configuration has to fix them",
+                        result.unrecorded_constants.join(", ")
+                    )?;
+                }
+            }
+            writeln!(out, "{} pairs checked, {} failed", results.len(), failed)
+        }
+        Format::Json => {
+            let results: Vec<_> = results
+                .iter()
+                .map(|result| {
+                    json!({
+                        "file": result.file,
+                        "update": result.update_name,
+                        "process_id": result.process_id,
+                        "pair": result.pair_index,
+                        "passed": result.error.is_none(),
+                        "error": result.error,
+                        "bundle": result.bundle,
+                        "unrecorded_constants": result.unrecorded_constants,
+                    })
+                })
+                .collect();
+            let report = json!({
+                "checked": results.len(),
+                "failed": failed,
+                "results": results,
+            });
+            writeln!(out, "{}", serde_json::to_string_pretty(&report)?)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(results) => {
+            if let Err(e) = report(args.format, &results, &mut io::stdout()) {
+                eprintln!("Can't write the report: {}", e);
+                return ExitCode::from(2);
+            }
+            if results.iter().all(|r| r.error.is_none()) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use tla_instrumentation::checker::FakeApalache;
+    use tla_instrumentation::{
+        write_trace_file, GlobalState, RecordedTrace, ResolvedStatePair, TlaConstantAssignment,
+        ToTla,
+    };
+
+    #[test]
+    fn checks_selected_pairs() {
+        let dir = std::env::temp_dir().join(format!("tla_check_cli_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let spec = dir.join("Counter.tla");
+        fs::write(
+            &spec,
+            "---- MODULE Counter ----\nEXTENDS Naturals\nCONSTANT STEP\nVARIABLE cnt\nNext == cnt' = cnt + STEP\n====\n",
+        )
+        .unwrap();
+        let fake = FakeApalache::new(&dir).deadlock_when("cnt' = 7");
+        let pair = |start: u64, end: u64| {
+            let state = |cnt: u64| {
+                let mut state = GlobalState::new();
+                state.add("cnt", cnt.to_tla_value());
+                state
+            };
+            ResolvedStatePair {
+                start: state(start),
+                end: state(end),
+            }
+        };
+        let trace = |update_name: &str, state_pairs| RecordedTrace {
+            update_name: update_name.to_string(),
+            process_id: "p1".to_string(),
+            canister_name: "counter".to_string(),
+            state_pairs,
+            // The reset trace leaves STEP to the configuration
+            constants: TlaConstantAssignment {
+                constants: if update_name == "reset" {
+                    BTreeMap::new()
+                } else {
+                    BTreeMap::from([("STEP".to_string(), 2_u64.to_tla_value())])
+                },
+            },
+        };
+        let traces = dir.join("traces.candid");
+        write_trace_file(
+            &traces,
+            &[
+                trace("increment", vec![pair(0, 2), pair(5, 7)]),
+                trace("reset", vec![pair(2, 4)]),
+            ],
+        )
+        .unwrap();
+        let args = |extra: &[&str]| {
+            let mut args = vec![
+                "tla-check".to_string(),
+                "--spec".to_string(),
+                spec.display().to_string(),
+                "--action".to_string(),
+                "Next".to_string(),
+                "--apalache".to_string(),
+                fake.write().unwrap().display().to_string(),
+                traces.display().to_string(),
+            ];
+            args.extend(extra.iter().map(|a| a.to_string()));
+            Args::parse_from(args)
+        };
+
+        let all = run(&args(&[])).unwrap();
+        let selected = run(&args(&["--update", "increment", "--pair", "0"])).unwrap();
+        let mut json = Vec::new();
+        report(Format::Json, &all, &mut json).unwrap();
+        let mut human = Vec::new();
+        report(Format::Human, &all, &mut human).unwrap();
+        for bundle in all.iter().filter_map(|r| r.bundle.as_ref()) {
+            fs::remove_dir_all(bundle).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let failed: Vec<_> = all
+            .iter()
+            .filter(|r| r.error.is_some())
+            .map(|r| (r.update_name.as_str(), r.pair_index))
+            .collect();
+        assert_eq!(all.len(), 3);
+        assert_eq!(failed, vec![("increment", 1)]);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].error, None);
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["results"][1]["passed"], false);
+        assert_eq!(json["results"][1]["unrecorded_constants"], json!([]));
+        assert_eq!(json["results"][2]["unrecorded_constants"], json!(["STEP"]));
+        assert!(String::from_utf8(human)
+            .unwrap()
+            .contains("Warning: constants STEP weren't recorded"));
+    }
+}
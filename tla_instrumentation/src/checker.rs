@@ -1,27 +1,122 @@
 // use ic_state_machine_tests::StateMachine;
 // use ic_test_utilities_load_wasm::load_wasm;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
+use crate::GlobalState;
 use crate::ResolvedStatePair;
 use crate::TlaConstantAssignment;
 
+mod actions;
+mod apalache_output;
+mod apalache_server;
+mod backend;
+mod batch;
+mod bundle;
+mod cache;
+mod config;
+mod declarations;
+mod fake;
+mod localization;
+mod multi_step;
+mod parameters;
+mod process;
+mod report;
+mod tla_module;
+mod work_dir;
+pub use actions::{match_actions, ActionCheckError, ActionFlag, ActionMatch};
+pub use apalache_output::{parse_failure, CheckFailure, FailureKind, SourceLocation};
+pub use apalache_server::{ApalacheServer, StandInServer};
+pub use backend::{Apalache, ModelChecker, Tlc, TransitionCheck};
+pub use batch::{check_traces, BatchFailure, BatchOptions, BatchReport, PairLocation, PairStatus};
+use bundle::{bundle_dir, complete_bundle, run_check};
+pub use bundle::{
+    itf_trace, itf_value, COMMAND_FILE_NAME, ITF_FILE_NAME, RERUN_SCRIPT_NAME, TLA_STATES_FILE_NAME,
+};
+pub use cache::{
+    check_tla_code_link_cached, CachePolicy, ResultCache, CACHE_DIR_ENV_VAR, CACHE_POLICY_ENV_VAR,
+};
+pub use config::{
+    CheckerConfig, Verbosity, APALACHE_BINARY_ENV_VAR, CONFIG_FILE_ENV_VAR, FEATURES_ENV_VAR,
+    JVM_OPTIONS_ENV_VAR, OUT_DIR_ENV_VAR, SMT_ENCODING_ENV_VAR, TIMEOUT_ENV_VAR, VERBOSITY_ENV_VAR,
+};
+pub use declarations::{check_declarations, DeclarationMismatch, UndeclaredName};
+pub use fake::{FakeApalache, FakeChecker, RecordedCheck, Verdict};
+pub use localization::{
+    check_tla_code_link_localized, localize_failure, Localization, VariableDiff,
+};
+pub use multi_step::{check_trace, TraceCheckError};
+pub use parameters::{ParameterBinding, PredicateParameter};
+pub use process::ProcessLimits;
+pub use report::{CaseOutcome, TestCase, TestReport};
+use tla_module::declared_constants;
+pub use tla_module::{spec_declarations, spec_labels, SpecDeclarations};
+use work_dir::WorkDir;
+pub use work_dir::{artifacts_dir, ARTIFACTS_DIR_ENV_VAR};
+
 pub trait HasTlaRepr {
     fn to_tla_state(&self) -> HashMap<String, String>;
 }
 
 #[derive(Debug)]
 pub enum ApalacheError {
-    CheckFailed(String),
+    CheckFailed(Box<CheckFailure>),
     SetupError(String),
+    /// The model checker was killed after running for too long
+    Timeout {
+        elapsed: Duration,
+        module: Box<Path>,
+    },
+    /// The recorded variables or constants don't match the spec's declarations, so the
+    /// model checker wasn't run
+    DeclarationMismatch(Box<DeclarationMismatch>),
+}
+
+impl ApalacheError {
+    /// Whether the model checker found that the transition isn't possible, as opposed to
+    /// failing to decide that
+    pub fn is_deadlock(&self) -> bool {
+        matches!(self, ApalacheError::CheckFailed(failure) if failure.kind == FailureKind::Deadlock)
+    }
+}
+
+impl std::fmt::Display for ApalacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApalacheError::CheckFailed(failure) => write!(f, "{}", failure),
+            ApalacheError::SetupError(e) => write!(f, "Setup error: {}", e),
+            ApalacheError::Timeout { elapsed, module } => write!(
+                f,
+                "The model checker timed out after {:?} when checking file\n{:?}",
+                elapsed, module
+            ),
+            ApalacheError::DeclarationMismatch(mismatch) => write!(f, "{}", mismatch),
+        }
+    }
 }
 
+#[derive(Debug)]
 pub struct TlaCheckError {
     pub apalache_error: ApalacheError,
     pub pair: ResolvedStatePair,
     pub constants: TlaConstantAssignment,
+    /// The variables responsible for the failure, if the failure was localised
+    pub localization: Option<Box<Localization>>,
+    /// The differences to the spec's declarations that didn't stop the check: constants
+    /// that weren't recorded, and so had to be fixed by the checker configuration
+    pub declaration_mismatch: Option<Box<DeclarationMismatch>>,
+}
+
+impl TlaCheckError {
+    /// The directory with everything needed to reproduce the failure without Rust: the
+    /// generated module, the spec, the states and constants, and a script re-running the
+    /// model checker. Only failed checks keep their directory.
+    pub fn bundle(&self) -> Option<PathBuf> {
+        bundle_dir(&self.apalache_error)
+    }
 }
 
 const INIT_PREDICATE_NAME: &str = "Check_Code_Link_Init";
@@ -57,96 +152,130 @@ fn mk_transition_predicate(
         .map(|(k, v)| format!("  /\\ {}' = {}", k, v))
         .collect::<Vec<_>>()
         .join("\n");
-    let old_transition_predicate =
-        add_parameters(old_transition_predicate, predicate_parameters.clone());
-    let new_next_predicate = add_parameters(new_next_predicate, predicate_parameters);
+    let old_transition_predicate = add_parameters(old_transition_predicate, predicate_parameters);
     format!(
         "{} ==\n  /\\ {}\n{}",
         new_next_predicate, old_transition_predicate, post_state_constraint,
     )
 }
 
-fn mk_constant_definitions(constants: HashMap<String, String>) -> Vec<String> {
-    constants
-        .iter()
-        .map(|(k, v)| format!("{} == {}", k, v))
-        .collect()
-}
+const CONSTANT_INIT_PREDICATE_NAME: &str = "Check_Code_Link_CInit";
 
-/* Check whether Apalache complains about deadlocks with traces of length one */
-fn run_apalache(
-    apalache_binary: &Path,
+/// Constants that the spec declares can't be redefined in the wrapper module, so they are
+/// fixed by a constant initialization predicate (Apalache's `--cinit`) instead. The other
+/// ones are defined as operators. Returns the definitions and the name of the constant
+/// initialization predicate, if one is needed.
+fn mk_constant_definitions(
     tla_module: &Path,
-    init_predicate: String,
-    next_predicate: String,
-) -> Result<(), ApalacheError> {
-    let mut cmd = Command::new(apalache_binary);
-    cmd.arg("check")
-        .arg(format!("--init={}", init_predicate))
-        .arg(format!("--next={}", next_predicate))
-        .arg("--length=1")
-        .arg(tla_module);
-    cmd.status()
-        .map_err(|e| ApalacheError::SetupError(e.to_string()))
-        .and_then(|e| {
-            if e.success() {
-                Ok(())
-            } else {
-                Err(ApalacheError::CheckFailed(
-                    format!(
-                        "When checking file\n{:?}\nApalache returned the error: {}",
-                        tla_module, e
-                    )
-                    .to_string(),
-                ))
-            }
-        })
+    constants: HashMap<String, String>,
+) -> Result<(Vec<String>, Option<String>), String> {
+    let declared = declared_constants(tla_module)?;
+    let sorted_constants: BTreeMap<_, _> = constants.into_iter().collect();
+    let (declared_values, undeclared_values): (Vec<_>, Vec<_>) = sorted_constants
+        .into_iter()
+        .partition(|(k, _)| declared.contains(k));
+    let mut definitions: Vec<String> = undeclared_values
+        .iter()
+        .map(|(k, v)| format!("{} == {}", k, v))
+        .collect();
+    if declared_values.is_empty() {
+        return Ok((definitions, None));
+    }
+    let constant_constraint = declared_values
+        .iter()
+        .map(|(k, v)| format!("  /\\ {} = {}", k, v))
+        .collect::<Vec<_>>()
+        .join("\n");
+    definitions.push(format!(
+        "{} ==\n{}",
+        CONSTANT_INIT_PREDICATE_NAME, constant_constraint
+    ));
+    Ok((definitions, Some(CONSTANT_INIT_PREDICATE_NAME.to_string())))
 }
 
+#[derive(Clone, Debug)]
 pub struct PredicateDescription {
     pub tla_module: PathBuf,
     pub transition_predicate: String,
-    pub predicate_parameters: Vec<String>,
+    pub predicate_parameters: Vec<PredicateParameter>,
 }
 
+/// Checks the state pair with Apalache, run as given by the configuration
 pub fn check_tla_code_link(
-    apalache: &Path,
+    config: &CheckerConfig,
     predicate: PredicateDescription,
     state_pair: ResolvedStatePair,
     constants: TlaConstantAssignment,
 ) -> Result<(), TlaCheckError> {
-    check_tla_code_link_raw(
-        apalache,
+    check_tla_code_link_with(
+        &Apalache::from_config(config.clone()),
+        predicate,
+        state_pair,
+        constants,
+    )
+}
+
+/// Like `check_tla_code_link`, but with an arbitrary model checker
+pub fn check_tla_code_link_with(
+    checker: &dyn ModelChecker,
+    predicate: PredicateDescription,
+    state_pair: ResolvedStatePair,
+    constants: TlaConstantAssignment,
+) -> Result<(), TlaCheckError> {
+    let mut declaration_mismatch = None;
+    check_recorded_names(
         &predicate.tla_module,
-        predicate.transition_predicate,
-        predicate.predicate_parameters,
-        state_pair
-            .start
-            .0
-             .0
-            .iter()
-            .map(|(k, v)| (k.clone(), v.to_string()))
-            .collect(),
-        state_pair
-            .end
-            .0
-             .0
-            .iter()
-            .map(|(k, v)| (k.clone(), v.to_string()))
-            .collect(),
-        constants
-            .constants
-            .iter()
-            .map(|(k, v)| (k.clone(), v.to_string()))
-            .collect(),
+        [&state_pair.start, &state_pair.end],
+        &constants,
     )
-    .map_err(|e| TlaCheckError {
-        apalache_error: e,
-        pair: state_pair,
-        constants,
+    .and_then(|mismatch| {
+        declaration_mismatch = mismatch;
+        predicate
+            .bind_parameters(&state_pair)
+            .map_err(ApalacheError::SetupError)
+    })
+    .and_then(|parameters| {
+        check_tla_code_link_raw_with(
+            checker,
+            &predicate.tla_module,
+            predicate.transition_predicate,
+            parameters,
+            state_pair.start.to_map(),
+            state_pair.end.to_map(),
+            constants.to_map(),
+        )
     })
+    .map_err(|e| {
+        complete_bundle(&e, &[&state_pair.start, &state_pair.end], &constants);
+        TlaCheckError {
+            apalache_error: e,
+            pair: state_pair,
+            constants,
+            localization: None,
+            declaration_mismatch,
+        }
+    })
+}
+
+/// Fails if the recorded names don't match the spec's declarations, as the model checker's
+/// errors are hard to make sense of in this case. Declared constants that weren't recorded
+/// are returned instead, as the configuration's `constant_init` may fix them.
+fn check_recorded_names<'a>(
+    tla_module: &Path,
+    states: impl IntoIterator<Item = &'a GlobalState>,
+    constants: &TlaConstantAssignment,
+) -> Result<Option<Box<DeclarationMismatch>>, ApalacheError> {
+    let mismatch =
+        check_declarations(tla_module, states, constants).map_err(ApalacheError::SetupError)?;
+    if mismatch.is_fatal() {
+        return Err(ApalacheError::DeclarationMismatch(Box::new(mismatch)));
+    }
+    Ok((!mismatch.is_empty()).then(|| Box::new(mismatch)))
 }
 
+/// Distinguishes the modules generated by concurrent checks within the same process
+static MODULE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 fn sha256_hex(input: Vec<u8>) -> String {
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
@@ -155,13 +284,79 @@ fn sha256_hex(input: Vec<u8>) -> String {
     format!("{:x}", result)
 }
 
+/// Writes a wrapper module into the working directory that extends the spec with the
+/// given definitions. The spec itself is left untouched.
+fn new_wrapper_module(
+    work_dir: &Path,
+    tla_module: &Path,
+    definitions: Vec<String>,
+) -> Result<PathBuf, String> {
+    let module_name = tla_module
+        .file_stem()
+        .ok_or(format!(
+            "Can't compute the module name of {}",
+            tla_module.display()
+        ))
+        .map(|n| n.to_string_lossy())?;
+    if !tla_module.exists() {
+        return Err(format!("The module {} doesn't exist", tla_module.display()));
+    }
+
+    // The name is made unique per check, so that concurrent checks of the same module
+    // don't clash
+    let content_hash = &sha256_hex(
+        definitions
+            .iter()
+            .flat_map(|s| s.as_bytes())
+            .cloned()
+            .collect(),
+    )[..32];
+    let wrapper_name = format!(
+        "Code_Link_{}_{}_{}_{}",
+        module_name,
+        content_hash,
+        std::process::id(),
+        MODULE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let wrapper = format!(
+        "---- MODULE {} ----\nEXTENDS {}\n\n{}\n\n====\n",
+        wrapper_name,
+        module_name,
+        definitions.join("\n\n")
+    );
+    let wrapper_path = work_dir.join(format!("{}.tla", wrapper_name));
+    fs::write(&wrapper_path, wrapper).map_err(|e| e.to_string())?;
+    Ok(wrapper_path)
+}
+
 /** Uses Apalache to check whether a trace step is allowed by the TLA+ transition.
  *
  * Returns an error if Apalache returns one, or if there's something wrong with
  * the setup.
  */
 pub fn check_tla_code_link_raw(
-    apalache: &Path,
+    config: &CheckerConfig,
+    tla_module: &Path,
+    transition_predicate: String,
+    predicate_parameters: Vec<String>,
+    pre_state: HashMap<String, String>,
+    post_state: HashMap<String, String>,
+    constants: HashMap<String, String>,
+) -> Result<(), ApalacheError> {
+    check_tla_code_link_raw_with(
+        &Apalache::from_config(config.clone()),
+        tla_module,
+        transition_predicate,
+        predicate_parameters,
+        pre_state,
+        post_state,
+        constants,
+    )
+}
+
+/// Like `check_tla_code_link_raw`, but with an arbitrary model checker
+pub fn check_tla_code_link_raw_with(
+    checker: &dyn ModelChecker,
     tla_module: &Path,
     transition_predicate: String,
     predicate_parameters: Vec<String>,
@@ -170,95 +365,85 @@ pub fn check_tla_code_link_raw(
     constants: HashMap<String, String>,
 ) -> Result<(), ApalacheError> {
     // The strategy:
-    // 1. Copy the given TLA+ to a temporary file in the same directory (so that module imports still work)
-    // 2. In the temporary file, create new Init and Next relations (under a different name) as follows:
+    // 1. Create a wrapper module in a fresh working directory that references the given TLA+ module and its
+    //    imports. The wrapper extends the given module, fixing the values of the constants
+    // 2. In the wrapper module, create new Init and Next relations (under a different name) as follows:
     //    a. initial state is equal to the given pre_state
     //    b. next is a conjunction of the given transition predicate, and the requirement that the post state
     //       equals the given post_state
-    // 3. Run Apalache on the result with a maximum trace length of 1 and check whether the model deadlocks; if it
-    //    does deadlock, it means that the transition is not compatible with our pre-and post states
-
-    fn new_module_with_predicates_and_constants(
-        tla_module: &Path,
-        predicates: Vec<String>,
-        constants: Vec<String>,
-    ) -> Result<PathBuf, String> {
-        let parent_dir = tla_module.parent().ok_or(format!(
-            "Can't get the parent directory of the alleged TLA module {}",
-            tla_module.display()
-        ))?;
-        let module_name = tla_module
-            .file_stem()
-            .ok_or(format!(
-                "Can't compute the module name of {}",
-                tla_module.display()
-            ))
-            .map(|n| n.to_string_lossy())?;
-
-        let module_text = fs::read_to_string(tla_module)
-            .map_err(|_e| format!("Couldn't read from module {}", tla_module.display()))?;
-        let (module_body, _comments) = module_text.split_once("\n====").ok_or(format!(
-            "No module end delimiter in the module {}",
-            tla_module.display()
-        ))?;
-
-        // Change the module name such that it fits the new file name
-        // TODO: use uuids or something to prevent clashes
-        let new_module_prefix = "Code_Link";
-        let new_module_suffix = &sha256_hex(
-            predicates
-                .iter()
-                .chain(constants.iter())
-                .flat_map(|s| s.as_bytes())
-                .cloned()
-                .collect(),
-        )[..32];
-        let module_body = module_body.replace(
-            format!("MODULE {}", module_name.clone()).as_str(),
-            format!(
-                "MODULE {}_{}_{}",
-                new_module_prefix, module_name, new_module_suffix
-            )
-            .as_str(),
-        );
-
-        let module_body = module_body.replace(
-            "\\* CODE_LINK_INSERT_CONSTANTS",
-            constants.join("\n").as_str(),
-        );
-        let new_module = format!("{}\n{}\n====", module_body, predicates.join("\n"));
-        let temp_file_path = parent_dir.join(format!(
-            "{}_{}_{}.tla",
-            new_module_prefix, module_name, new_module_suffix
-        ));
-        fs::write(temp_file_path.clone(), new_module).map_err(|e| e.to_string())?;
-        Ok(temp_file_path.into())
-    }
-
+    // 3. Run the model checker on the result with a maximum trace length of 1 and check whether the model
+    //    deadlocks; if it does deadlock, it means that the transition is not compatible with our pre-and post
+    //    states. Backends can add their own definitions to the module for this, e.g., TLC needs a next predicate
+    //    that can stutter after the transition.
+    // 4. Remove the working directory if the check passed, and keep it as an artifact otherwise
+    let (constant_definitions, constant_init) =
+        mk_constant_definitions(tla_module, constants.clone())
+            .map_err(ApalacheError::SetupError)?;
+    let check = TransitionCheck {
+        init_predicate: INIT_PREDICATE_NAME.to_string(),
+        next_predicate: NEXT_PREDICATE_NAME.to_string(),
+        post_state: post_state.clone(),
+        constants,
+        length: 1,
+        constant_init,
+    };
+    let extra_definitions = checker
+        .extra_definitions(tla_module, &check)
+        .map_err(ApalacheError::SetupError)?;
     let init_predicate = mk_init_predicate(pre_state);
     let trans_predicate =
         mk_transition_predicate(post_state, transition_predicate, predicate_parameters);
-    let constant_definitions = mk_constant_definitions(constants);
-    let new_module = new_module_with_predicates_and_constants(
+    let work_dir = WorkDir::new(tla_module).map_err(ApalacheError::SetupError)?;
+    let result = new_wrapper_module(
+        work_dir.path(),
         tla_module,
-        vec![init_predicate, trans_predicate],
-        constant_definitions,
+        constant_definitions
+            .into_iter()
+            .chain([init_predicate, trans_predicate])
+            .chain(extra_definitions)
+            .collect(),
     )
-    .map_err(|e| ApalacheError::SetupError(e))?;
-    run_apalache(
-        apalache,
-        new_module.as_path(),
-        INIT_PREDICATE_NAME.to_string(),
-        NEXT_PREDICATE_NAME.to_string(),
-    )?;
-    // TODO: remove the temporary file if everything went well
-    // fs::remove_file(new_module);
-    Ok(())
+    .map_err(ApalacheError::SetupError)
+    .and_then(|new_module| run_check(checker, &new_module, &check));
+    work_dir.finish(result, &artifacts_dir())
 }
 
 #[test]
 fn retrieve_btc() {}
 
+#[test]
+fn wrapper_module_extends_spec() {
+    let dir = crate::test_helpers::test_dir("wrapper");
+    let spec =
+        "---- MODULE Counter ----\nCONSTANT MAX\nVARIABLE cnt\n\\* Counter mentioned\n====\n====\n";
+    let module = dir.join("Counter.tla");
+    fs::write(&module, spec).unwrap();
+    let (definitions, constant_init) = mk_constant_definitions(
+        &module,
+        HashMap::from([
+            ("MAX".to_string(), "3".to_string()),
+            ("STEP".to_string(), "1".to_string()),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        constant_init,
+        Some(CONSTANT_INIT_PREDICATE_NAME.to_string())
+    );
+    let wrapper = new_wrapper_module(&dir, &module, definitions).unwrap();
+    let wrapper_text = fs::read_to_string(&wrapper).unwrap();
+    let wrapper_name = wrapper.file_stem().unwrap().to_string_lossy().to_string();
+    assert_eq!(fs::read_to_string(&module).unwrap(), spec);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        wrapper_text,
+        format!(
+            "---- MODULE {} ----\nEXTENDS Counter\n\nSTEP == 1\n\nCheck_Code_Link_CInit ==\n  /\\ MAX = 3\n\n====\n",
+            wrapper_name
+        )
+    );
+}
+
 #[cfg(test)]
 impl HasTlaRepr for TlaCounterState {
     fn to_tla_state(&self) -> HashMap<String, String> {
@@ -271,28 +456,58 @@ struct TlaCounterState {
     cnt: u32,
 }
 
+#[cfg(unix)]
 #[test]
 fn basic_test() {
-    let pre_state = TlaCounterState { cnt: 4 };
-    let post_state = TlaCounterState { cnt: 6 };
-    let apalache = get_apalache_path();
-    let tla_module = project_root()
-        .join("rs")
-        .join("tla_code_link_poc")
-        .join("tla")
-        .join("Counter.tla");
-    let result = check_tla_code_link_raw(
-        &apalache,
+    let dir = crate::test_helpers::test_dir("basic");
+    let tla_module = dir.join("Counter.tla");
+    fs::write(
         &tla_module,
-        "Next".to_string(),
-        vec![],
-        pre_state.to_tla_state(),
-        post_state.to_tla_state(),
-        HashMap::default(),
-    );
+        "---- MODULE Counter ----\nEXTENDS Naturals\nVARIABLE cnt\nNext == cnt' = cnt + 2\n====\n",
+    )
+    .unwrap();
+    // Stands in for Apalache, which would reject any post-state other than 6
+    let fake = FakeApalache::new(&dir).deadlock_when("cnt' = 7");
+    let config = CheckerConfig::new(&fake.write().unwrap());
+    let check = |post_state: TlaCounterState| {
+        check_tla_code_link_raw(
+            &config,
+            &tla_module,
+            "Next".to_string(),
+            vec![],
+            TlaCounterState { cnt: 4 }.to_tla_state(),
+            post_state.to_tla_state(),
+            HashMap::default(),
+        )
+    };
+
+    let result = check(TlaCounterState { cnt: 6 });
     assert!(
         result.is_ok(),
         "Apalache returned an error: {:?}",
         result.unwrap_err()
     );
+    match check(TlaCounterState { cnt: 7 }) {
+        Err(ApalacheError::CheckFailed(failure)) => {
+            assert_eq!(failure.kind, FailureKind::Deadlock);
+            assert_eq!(failure.exit_code, Some(12));
+            // The kept directory reproduces the failure on its own
+            let bundle = failure.module.parent().unwrap();
+            let spec = fs::symlink_metadata(bundle.join("Counter.tla")).unwrap();
+            assert!(spec.file_type().is_file());
+            let rerun = std::process::Command::new(bundle.join(RERUN_SCRIPT_NAME))
+                .output()
+                .unwrap();
+            assert_eq!(rerun.status.code(), Some(12));
+            fs::remove_dir_all(bundle).unwrap();
+        }
+        other => panic!("Expected a deadlock, got {:?}", other),
+    }
+    let calls = fake.calls();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(calls.len(), 3);
+    // The re-run checks the same module, from within the bundle
+    assert!(calls[1].ends_with(calls[2].rsplit(' ').next().unwrap()));
+    assert!(calls[0]
+        .starts_with("check --init=Check_Code_Link_Init --next=Check_Code_Link_Next --length=1 "));
 }
@@ -0,0 +1,193 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use super::work_dir::discard_artifacts;
+use super::{check_tla_code_link_with, ModelChecker, PredicateDescription, TlaCheckError};
+use crate::{ResolvedStatePair, TlaConstantAssignment};
+
+/// The result of checking a step against several candidate actions. Actions are named by
+/// their predicate applied to its parameters, e.g., `Transfer("alice", 5)`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActionMatch {
+    /// The actions that allow the step
+    pub accepted: BTreeSet<String>,
+    /// The actions that don't
+    pub rejected: BTreeSet<String>,
+}
+
+/// A step that doesn't match the actions it was expected to match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ActionFlag {
+    /// None of the candidate actions allows the step
+    NoMatch,
+    /// The step is only allowed by actions that weren't expected
+    Unexpected { accepted: BTreeSet<String> },
+}
+
+impl Display for ActionFlag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionFlag::NoMatch => write!(f, "No candidate action allows the step"),
+            ActionFlag::Unexpected { accepted } => write!(
+                f,
+                "The step is only allowed by unexpected actions: {}",
+                accepted.iter().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl ActionMatch {
+    /// Flags the step if no action allows it, or if none of the expected actions do. If
+    /// no actions are expected, any match is fine.
+    pub fn flag(&self, expected: &BTreeSet<String>) -> Option<ActionFlag> {
+        if self.accepted.is_empty() {
+            Some(ActionFlag::NoMatch)
+        } else if !expected.is_empty() && self.accepted.is_disjoint(expected) {
+            Some(ActionFlag::Unexpected {
+                accepted: self.accepted.clone(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A check of a candidate action that failed for another reason than the action not
+/// allowing the step
+#[derive(Debug)]
+pub struct ActionCheckError {
+    pub action: String,
+    pub error: Box<TlaCheckError>,
+}
+
+/// Checks the state pair against each of the candidate actions, running up to `workers`
+/// model checker processes in parallel, and returns which of them allow it. This tells
+/// which disjunct of, e.g., a `Next` predicate a step took. Fails if any check fails
+/// for another reason than a deadlock.
+pub fn match_actions(
+    checker: &dyn ModelChecker,
+    candidates: &[PredicateDescription],
+    state_pair: &ResolvedStatePair,
+    constants: &TlaConstantAssignment,
+    workers: usize,
+) -> Result<ActionMatch, ActionCheckError> {
+    let next_candidate = AtomicUsize::new(0);
+    let result = Mutex::new(ActionMatch::default());
+    let errors: Mutex<Vec<(usize, ActionCheckError)>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, candidates.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_candidate.fetch_add(1, Ordering::SeqCst);
+                let Some(candidate) = candidates.get(index) else {
+                    break;
+                };
+                // If the parameters can't be bound, the check fails below
+                let action = candidate
+                    .transition_for(state_pair)
+                    .unwrap_or(candidate.transition_predicate.clone());
+                match check_tla_code_link_with(
+                    checker,
+                    candidate.clone(),
+                    state_pair.clone(),
+                    constants.clone(),
+                ) {
+                    Ok(()) => {
+                        result.lock().unwrap().accepted.insert(action);
+                    }
+                    Err(error) if error.apalache_error.is_deadlock() => {
+                        // Most candidates are expected to reject the step
+                        discard_artifacts(&error.apalache_error);
+                        result.lock().unwrap().rejected.insert(action);
+                    }
+                    Err(error) => errors.lock().unwrap().push((
+                        index,
+                        ActionCheckError {
+                            action,
+                            error: Box::new(error),
+                        },
+                    )),
+                }
+            });
+        }
+    });
+    // Report the error of the first failing candidate, independently of the scheduling
+    match errors
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .min_by_key(|(index, _)| *index)
+    {
+        Some((_, error)) => Err(error),
+        None => Ok(result.into_inner().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{FailureKind, FakeChecker, Verdict};
+    use crate::test_helpers::{no_constants, test_dir};
+    use crate::{GlobalState, ToTla};
+
+    #[test]
+    fn finds_accepting_actions() {
+        let dir = test_dir("actions");
+        let module = dir.join("Ledger.tla");
+        std::fs::write(&module, "---- MODULE Ledger ----\nVARIABLE balance\n====\n").unwrap();
+        let candidate = |predicate: &str, parameters: &[&str]| PredicateDescription {
+            tla_module: module.clone(),
+            transition_predicate: predicate.to_string(),
+            predicate_parameters: parameters.iter().map(|p| (*p).into()).collect(),
+        };
+        let candidates = vec![
+            candidate("Deposit", &["5"]),
+            candidate("Withdraw", &["5"]),
+            candidate("Noop", &[]),
+        ];
+        let state = |balance: u64| {
+            let mut state = GlobalState::new();
+            state.add("balance", balance.to_tla_value());
+            state
+        };
+        let pair = ResolvedStatePair {
+            start: state(10),
+            end: state(5),
+        };
+        let constants = no_constants();
+        let checker = FakeChecker::new()
+            .with_default(Verdict::Fail(FailureKind::Deadlock))
+            .when_contains("/\\ Withdraw(5)", Verdict::Pass);
+
+        let matched = match_actions(&checker, &candidates, &pair, &constants, 2).unwrap();
+        let failing = FakeChecker::new()
+            .when_contains("/\\ Noop", Verdict::Fail(FailureKind::TypeCheckError));
+        let error = match_actions(&failing, &candidates, &pair, &constants, 2).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            matched.accepted,
+            BTreeSet::from(["Withdraw(5)".to_string()])
+        );
+        assert_eq!(
+            matched.rejected,
+            BTreeSet::from(["Deposit(5)".to_string(), "Noop".to_string()])
+        );
+        assert_eq!(matched.flag(&BTreeSet::new()), None);
+        assert_eq!(
+            matched.flag(&BTreeSet::from(["Deposit(5)".to_string()])),
+            Some(ActionFlag::Unexpected {
+                accepted: matched.accepted.clone()
+            })
+        );
+        assert_eq!(
+            ActionMatch::default().flag(&BTreeSet::new()),
+            Some(ActionFlag::NoMatch)
+        );
+        assert_eq!(error.action, "Noop");
+    }
+}
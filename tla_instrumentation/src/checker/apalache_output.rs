@@ -0,0 +1,324 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// The reason why a model checker run failed, as far as it can be told from its output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// No transition leads from the pre-state to the post-state, i.e., the state pair
+    /// isn't allowed by the spec
+    Deadlock,
+    TypeCheckError,
+    ParseError {
+        location: Option<SourceLocation>,
+    },
+    /// An operator or a constant that is used but not defined
+    MissingDefinition {
+        name: String,
+    },
+    OutOfMemory,
+    Unknown,
+}
+
+/// Where in the checked modules an error was reported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub line: u32,
+    pub column: u32,
+    /// The module containing the location, which may be the spec, a module it extends
+    /// or the generated wrapper. Not every message names it.
+    pub module: Option<String>,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)?;
+        if let Some(module) = &self.module {
+            write!(f, " of module {}", module)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::Deadlock => write!(f, "the transition is not enabled (deadlock)"),
+            FailureKind::TypeCheckError => write!(f, "type checking failed"),
+            FailureKind::ParseError {
+                location: Some(location),
+            } => write!(f, "parse error at {}", location),
+            FailureKind::ParseError { location: None } => write!(f, "parse error"),
+            FailureKind::MissingDefinition { name } => {
+                write!(f, "missing operator or constant {}", name)
+            }
+            FailureKind::OutOfMemory => write!(f, "the model checker ran out of memory"),
+            FailureKind::Unknown => write!(f, "unknown failure"),
+        }
+    }
+}
+
+/// A failed model checker run, with the part of its output that explains the failure
+#[derive(Clone, Debug)]
+pub struct CheckFailure {
+    pub kind: FailureKind,
+    /// The exit code of the model checker, if it exited normally
+    pub exit_code: Option<i32>,
+    /// The generated module that was checked
+    pub module: PathBuf,
+    /// The lines of the output relevant to the failure
+    pub excerpt: String,
+    /// The complete output (stdout followed by stderr)
+    pub output: String,
+}
+
+impl Display for CheckFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "When checking file\n{:?}\nthe model checker reported {} (exit code {})\n{}",
+            self.module,
+            self.kind,
+            self.exit_code
+                .map(|c| c.to_string())
+                .unwrap_or("none".to_string()),
+            self.excerpt
+        )
+    }
+}
+
+const EXCERPT_CONTEXT: usize = 3;
+const EXCERPT_TAIL: usize = 20;
+
+fn excerpt_around(lines: &[&str], index: usize) -> String {
+    let end = (index + EXCERPT_CONTEXT + 1).min(lines.len());
+    lines[index.saturating_sub(EXCERPT_CONTEXT)..end].join("\n")
+}
+
+/// The number at the start of the text, after skipping anything else
+fn leading_number(text: &str) -> Option<(u32, &str)> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let digits = &text[start..];
+    let end = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    Some((digits[..end].parse().ok()?, &digits[end..]))
+}
+
+/// Extracts "line L, col(umn) C" and the module name of the "... of module M" or
+/// "... in module M" that follows it, if any. For ranges like "line 3, col 5 to line 3,
+/// col 10 of module M", the start of the range is returned.
+fn parse_location(line: &str) -> Option<SourceLocation> {
+    let rest = &line[line.find("line ")? + "line ".len()..];
+    let (line_number, rest) = leading_number(rest)?;
+    let rest = rest.strip_prefix(',')?.trim_start().strip_prefix("col")?;
+    let (column, rest) = leading_number(rest)?;
+    let module = rest
+        .find("module ")
+        .map(|i| &rest[i + "module ".len()..])
+        .map(|after| {
+            after
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty());
+    Some(SourceLocation {
+        line: line_number,
+        column,
+        module,
+    })
+}
+
+/// Extracts an identifier following the given marker, stripping quotes and backticks
+fn identifier_after(line: &str, marker: &str) -> Option<String> {
+    let start = line.find(marker)? + marker.len();
+    let name: String = line[start..]
+        .trim_start_matches([' ', '`', '\'', '"'])
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn classify_line(line: &str) -> Option<FailureKind> {
+    if line.contains("java.lang.OutOfMemoryError") || line.contains("GC overhead limit exceeded") {
+        return Some(FailureKind::OutOfMemory);
+    }
+    for marker in [
+        "Unknown operator:",
+        "Operator not found:",
+        "Undefined operator",
+    ] {
+        if let Some(name) = identifier_after(line, marker) {
+            return Some(FailureKind::MissingDefinition { name });
+        }
+    }
+    if line.contains("not found") || line.contains("is not initialized") {
+        for marker in ["Operator ", "operator ", "Constant ", "constant "] {
+            if let Some(name) = identifier_after(line, marker) {
+                return Some(FailureKind::MissingDefinition { name });
+            }
+        }
+    }
+    if line.contains("Typing input error")
+        || line.contains("Type checker [FAILED]")
+        || line.contains("Typing error")
+    {
+        return Some(FailureKind::TypeCheckError);
+    }
+    if line.contains("Parse Error")
+        || line.contains("Parsing error")
+        || line.contains("Error by TLA+ parser")
+        || line.contains("Lexical error")
+        || line.starts_with("Encountered ")
+    {
+        return Some(FailureKind::ParseError {
+            location: parse_location(line),
+        });
+    }
+    if line.contains("Found a deadlock")
+        || line.contains("The outcome is: Deadlock")
+        || line.contains("Deadlock reached")
+    {
+        return Some(FailureKind::Deadlock);
+    }
+    None
+}
+
+/// Determines why a model checker run failed from its output. The first line that
+/// identifies a failure wins; parse errors without a location on the same line pick
+/// up the first location reported on the lines after it.
+pub fn parse_failure(output: &str) -> (FailureKind, String) {
+    let lines: Vec<&str> = output.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(kind) = classify_line(line) {
+            let kind = match kind {
+                FailureKind::ParseError { location: None } => FailureKind::ParseError {
+                    location: lines[i + 1..]
+                        .iter()
+                        .take(EXCERPT_CONTEXT)
+                        .find_map(|l| parse_location(l)),
+                },
+                kind => kind,
+            };
+            return (kind, excerpt_around(&lines, i));
+        }
+    }
+    let tail = lines[lines.len().saturating_sub(EXCERPT_TAIL)..].join("\n");
+    (FailureKind::Unknown, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_deadlock() {
+        let output = "\
+PASS #13: BoundedChecker
+Step 0: picking a transition out of 1 transition(s)
+State 1: Checking 1 state invariants
+Step 1: picking a transition out of 1 transition(s)
+Found a deadlock.
+Check the trace in: _apalache-out/Counter.tla/counterexample1.tla
+The outcome is: Deadlock
+EXITCODE: ERROR (12)";
+        let (kind, excerpt) = parse_failure(output);
+        assert_eq!(kind, FailureKind::Deadlock);
+        assert!(excerpt.contains("Found a deadlock."));
+        assert!(excerpt.contains("counterexample1.tla"));
+        // TLC's report
+        let (kind, _) = parse_failure(
+            "Computing initial states...\nError: Deadlock reached.\nError: The behavior up to this point is:",
+        );
+        assert_eq!(kind, FailureKind::Deadlock);
+    }
+
+    #[test]
+    fn recognizes_parse_error_with_location() {
+        let output = "\
+PASS #0: SanyParser
+Parsing file /tmp/Code_Link_Counter_abc.tla
+***Parse Error***
+Encountered \"==\" at line 12, column 9 in module Code_Link_Counter_abc
+Residual stack trace follows:
+EXITCODE: ERROR (255)";
+        let (kind, _) = parse_failure(output);
+        assert_eq!(
+            kind,
+            FailureKind::ParseError {
+                location: Some(SourceLocation {
+                    line: 12,
+                    column: 9,
+                    module: Some("Code_Link_Counter_abc".to_string()),
+                })
+            }
+        );
+        // Errors in modules that the spec extends are attributed to them
+        let (kind, _) = parse_failure(
+            "Error by TLA+ parser
+Unknown operator at line 4, col 7 to line 4, col 12 of module Common
+",
+        );
+        assert_eq!(
+            kind,
+            FailureKind::ParseError {
+                location: Some(SourceLocation {
+                    line: 4,
+                    column: 7,
+                    module: Some("Common".to_string()),
+                })
+            }
+        );
+        assert_eq!(
+            kind.to_string(),
+            "parse error at line 4, column 7 of module Common"
+        );
+        assert_eq!(
+            parse_location("Lexical error at line 2, column 3."),
+            Some(SourceLocation {
+                line: 2,
+                column: 3,
+                module: None,
+            })
+        );
+    }
+
+    #[test]
+    fn recognizes_missing_definitions() {
+        let (kind, _) = parse_failure("Unknown operator: `MAX_COUNTER'.\nEXITCODE: ERROR (255)");
+        assert_eq!(
+            kind,
+            FailureKind::MissingDefinition {
+                name: "MAX_COUNTER".to_string()
+            }
+        );
+        let (kind, _) = parse_failure("Error: Operator Next not found (used as transition)");
+        assert_eq!(
+            kind,
+            FailureKind::MissingDefinition {
+                name: "Next".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn recognizes_type_errors_and_oom() {
+        let (kind, _) = parse_failure(
+            "Typing input error: Expected a type annotation for VARIABLE cnt\nType checker [FAILED]",
+        );
+        assert_eq!(kind, FailureKind::TypeCheckError);
+        let (kind, _) = parse_failure(
+            "Exception in thread \"main\" java.lang.OutOfMemoryError: Java heap space",
+        );
+        assert_eq!(kind, FailureKind::OutOfMemory);
+        let (kind, excerpt) = parse_failure("something\nwent wrong");
+        assert_eq!(kind, FailureKind::Unknown);
+        assert_eq!(excerpt, "something\nwent wrong");
+    }
+}
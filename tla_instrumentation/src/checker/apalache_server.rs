@@ -0,0 +1,760 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use super::tla_module::{keyword_arguments, module_closure, spec_declarations};
+use super::{
+    parse_failure, Apalache, ApalacheError, CheckFailure, FailureKind, ModelChecker,
+    TransitionCheck,
+};
+
+/// How long to wait for a freshly started server to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Apalache running in its server mode (`apalache-mc server --server-type=explorer`),
+/// which keeps one JVM around for all checks. Each check loads the generated module in a
+/// new session over the JSON-RPC interface, assumes the init transition, and then asks,
+/// for each step, whether any next transition is enabled. If none is, the check fails
+/// with a `FailureKind::Deadlock`, just like a `check --length=N` run would.
+///
+/// The server has no counterpart to `--cinit`, so the values of the constants that the
+/// spec declares are substituted by instantiating the spec instead (see `instance_module`).
+///
+/// If the server was started by us, it is stopped when this value is dropped.
+pub struct ApalacheServer {
+    address: SocketAddr,
+    process: Option<Child>,
+    version: String,
+}
+
+#[derive(Debug)]
+enum RpcError {
+    /// The server couldn't be reached, or didn't answer with JSON-RPC
+    Transport(String),
+    /// The server answered with a JSON-RPC error
+    Response(String),
+}
+
+impl ApalacheServer {
+    /// Starts the server on the given local port and waits until it accepts connections
+    pub fn start(apalache: &Path, port: u16) -> Result<Self, String> {
+        let mut process = Command::new(apalache)
+            .arg("server")
+            .arg("--server-type=explorer")
+            .arg(format!("--port={}", port))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Couldn't start {}: {}", apalache.display(), e))?;
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            if let Ok(Some(status)) = process.try_wait() {
+                return Err(format!("The Apalache server exited with {}", status));
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                let _ = process.kill();
+                return Err(format!(
+                    "The Apalache server didn't accept connections on port {} within {:?}",
+                    port, STARTUP_TIMEOUT
+                ));
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        Ok(Self {
+            address,
+            process: Some(process),
+            version: format!("server {}", Apalache::new(apalache).version()),
+        })
+    }
+
+    /// Uses a server that is already running
+    pub fn connect(address: SocketAddr) -> Self {
+        Self {
+            address,
+            process: None,
+            version: format!("server at {}", address),
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        static REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        });
+        let body = http_post(self.address, "/rpc", &request.to_string())
+            .map_err(|e| RpcError::Transport(format!("{} failed: {}", method, e)))?;
+        let mut response: Value = serde_json::from_str(&body).map_err(|e| {
+            RpcError::Transport(format!(
+                "{} returned invalid JSON ({}): {}",
+                method, e, body
+            ))
+        })?;
+        if let Some(error) = response.get("error") {
+            let message = error
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| error.to_string());
+            return Err(RpcError::Response(message));
+        }
+        match response.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(RpcError::Transport(format!(
+                "{} returned neither a result nor an error: {}",
+                method, body
+            ))),
+        }
+    }
+
+    /// Assumes the first of the transitions that is enabled in the current snapshot,
+    /// rolling back after each disabled one. Returns whether one was enabled.
+    fn assume_any(
+        &self,
+        session: &Value,
+        snapshot: &Value,
+        transitions: &[Value],
+        log: &mut Vec<String>,
+    ) -> Result<bool, RpcError> {
+        for transition in transitions {
+            let result = self.call(
+                "assumeTransition",
+                json!({
+                    "sessionId": session,
+                    "transitionId": transition.get("index").cloned().unwrap_or(json!(0)),
+                    "checkEnabled": true,
+                }),
+            )?;
+            log.push(format!("assumeTransition: {}", result));
+            if result.get("status").and_then(Value::as_str) == Some("ENABLED") {
+                return Ok(true);
+            }
+            self.call(
+                "rollback",
+                json!({"sessionId": session, "snapshotId": snapshot}),
+            )?;
+        }
+        Ok(false)
+    }
+
+    fn run_session(
+        &self,
+        session: &Value,
+        spec: &Value,
+        check_length: usize,
+        log: &mut Vec<String>,
+    ) -> Result<bool, RpcError> {
+        let parameters = &spec["specParameters"];
+        let transitions = |name: &str| -> Vec<Value> {
+            parameters
+                .get(name)
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default()
+        };
+        if !self.assume_any(
+            session,
+            &spec["snapshotId"],
+            &transitions("initTransitions"),
+            log,
+        )? {
+            return Ok(false);
+        }
+        for _ in 0..check_length {
+            let step = self.call("nextStep", json!({ "sessionId": session }))?;
+            log.push(format!("nextStep: {}", step));
+            if !self.assume_any(
+                session,
+                &step["snapshotId"],
+                &transitions("nextTransitions"),
+                log,
+            )? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for ApalacheServer {
+    fn drop(&mut self) {
+        if let Some(process) = &mut self.process {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+/// Writes a module next to the generated one that instantiates the spec with the recorded
+/// constant values (`INSTANCE Spec WITH C <- v`) rather than extending it, and that
+/// otherwise has the same definitions, minus the constant initialization predicate.
+/// Declared constants without a recorded value stay parameters of the new module.
+fn instance_module(
+    generated_module: &Path,
+    check: &TransitionCheck,
+    constant_init: &str,
+) -> Result<PathBuf, String> {
+    let text = fs::read_to_string(generated_module).map_err(|e| {
+        format!(
+            "Couldn't read from module {}: {}",
+            generated_module.display(),
+            e
+        )
+    })?;
+    let dir = generated_module.parent().unwrap_or(Path::new("."));
+    let spec_name = keyword_arguments(&text, "EXTENDS")
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{} extends no module", generated_module.display()))?;
+    let spec = dir.join(format!("{}.tla", spec_name));
+    let declarations = spec_declarations(&spec)?;
+    // The standard modules aren't instantiated along with the spec's definitions, as the
+    // generated definitions may use them as well
+    let mut standard_modules = Vec::new();
+    for file in module_closure(&spec)? {
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+        for module in keyword_arguments(&text, "EXTENDS") {
+            if !dir.join(format!("{}.tla", module)).exists() && !standard_modules.contains(&module)
+            {
+                standard_modules.push(module);
+            }
+        }
+    }
+    let (substituted, parameters): (Vec<_>, Vec<_>) = declarations
+        .constants
+        .iter()
+        .partition(|name| check.constants.contains_key(*name));
+    let substitutions = substituted
+        .iter()
+        .map(|name| format!("{} <- {}", name, check.constants[*name]))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let module_name = format!(
+        "{}_Instance",
+        generated_module
+            .file_stem()
+            .ok_or_else(|| format!("Not a file: {}", generated_module.display()))?
+            .to_string_lossy()
+    );
+    let mut lines = vec![format!("---- MODULE {} ----", module_name)];
+    if !standard_modules.is_empty() {
+        lines.push(format!("EXTENDS {}", standard_modules.join(", ")));
+    }
+    if !parameters.is_empty() {
+        lines.push(format!(
+            "CONSTANTS {}",
+            parameters
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if !declarations.variables.is_empty() {
+        lines.push(format!(
+            "VARIABLES {}",
+            declarations
+                .variables
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    lines.push(format!("INSTANCE {} WITH {}", spec_name, substitutions));
+    // The generated module has a header, then the definitions separated by blank lines
+    let definitions = text
+        .trim_end()
+        .trim_end_matches('=')
+        .split("\n\n")
+        .skip(1)
+        .filter(|definition| {
+            !definition.is_empty() && !definition.starts_with(&format!("{} ==", constant_init))
+        })
+        .collect::<Vec<_>>();
+    let instance = format!(
+        "{}\n\n{}\n\n====\n",
+        lines.join("\n"),
+        definitions.join("\n\n")
+    );
+    let path = dir.join(format!("{}.tla", module_name));
+    fs::write(&path, instance).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+fn check_failed(
+    module: &Path,
+    kind: FailureKind,
+    excerpt: String,
+    output: String,
+) -> ApalacheError {
+    ApalacheError::CheckFailed(Box::new(CheckFailure {
+        kind,
+        exit_code: None,
+        module: module.to_path_buf(),
+        excerpt,
+        output,
+    }))
+}
+
+impl ModelChecker for ApalacheServer {
+    fn check(&self, generated_module: &Path, check: &TransitionCheck) -> Result<(), ApalacheError> {
+        let root_module = match &check.constant_init {
+            Some(constant_init) => instance_module(generated_module, check, constant_init)
+                .map_err(ApalacheError::SetupError)?,
+            None => generated_module.to_path_buf(),
+        };
+        // The root module comes first, followed by the modules it imports
+        let sources = module_closure(&root_module)
+            .map_err(ApalacheError::SetupError)?
+            .iter()
+            .map(|file| {
+                fs::read_to_string(file)
+                    .map(|text| BASE64.encode(text))
+                    .map_err(|e| {
+                        ApalacheError::SetupError(format!(
+                            "Couldn't read from module {}: {}",
+                            file.display(),
+                            e
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let spec = match self.call(
+            "loadSpec",
+            json!({
+                "sources": sources,
+                "init": check.init_predicate,
+                "next": check.next_predicate,
+                "invariants": [],
+                "exports": [],
+            }),
+        ) {
+            Ok(spec) => spec,
+            // The server reports parser and type checker errors in the error message
+            Err(RpcError::Response(message)) => {
+                let (kind, excerpt) = parse_failure(&message);
+                return Err(check_failed(generated_module, kind, excerpt, message));
+            }
+            Err(RpcError::Transport(e)) => return Err(ApalacheError::SetupError(e)),
+        };
+        let session = spec["sessionId"].clone();
+        let mut log = vec![format!("loadSpec: {}", spec)];
+        let outcome = self.run_session(&session, &spec, check.length, &mut log);
+        // Failing to dispose of the session only leaks memory on the server
+        let _ = self.call("disposeSpec", json!({ "sessionId": session }));
+        match outcome {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(check_failed(
+                generated_module,
+                FailureKind::Deadlock,
+                format!(
+                    "No transition of {} is enabled after {}",
+                    check.next_predicate, check.init_predicate
+                ),
+                log.join("\n"),
+            )),
+            Err(RpcError::Response(message)) => {
+                log.push(message.clone());
+                let (kind, excerpt) = parse_failure(&message);
+                Err(check_failed(
+                    generated_module,
+                    kind,
+                    excerpt,
+                    log.join("\n"),
+                ))
+            }
+            Err(RpcError::Transport(e)) => Err(ApalacheError::SetupError(e)),
+        }
+    }
+
+    fn version(&self) -> String {
+        self.version.clone()
+    }
+}
+
+/// A minimal HTTP/1.1 POST, as we only ever talk to a server on the local machine
+fn http_post(address: SocketAddr, path: &str, body: &str) -> Result<String, String> {
+    let mut stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        address,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| e.to_string())?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| format!("Malformed HTTP response: {}", response))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("HTTP error {}: {}", status, body));
+    }
+    if head
+        .to_ascii_lowercase()
+        .contains("transfer-encoding: chunked")
+    {
+        dechunk(body)
+    } else {
+        Ok(body.to_string())
+    }
+}
+
+fn dechunk(mut body: &str) -> Result<String, String> {
+    let mut result = String::new();
+    loop {
+        let (size, rest) = body
+            .split_once("\r\n")
+            .ok_or_else(|| "Malformed chunked body".to_string())?;
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|e| format!("Malformed chunk size: {}", e))?;
+        if size == 0 {
+            return Ok(result);
+        }
+        let chunk = rest
+            .get(..size)
+            .ok_or_else(|| "Truncated chunked body".to_string())?;
+        result.push_str(chunk);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+}
+
+/// A stand-in for the Apalache server that speaks the same JSON-RPC protocol, for tests
+/// that shouldn't depend on a Java installation. It doesn't evaluate TLA+: the given
+/// function decides, based on the text of the loaded modules, whether the transition
+/// is enabled (`Ok(true)`), disabled (`Ok(false)`) or whether loading the spec fails
+/// with the returned message.
+pub struct StandInServer {
+    address: SocketAddr,
+    modules: Arc<Mutex<Vec<String>>>,
+}
+
+/// Per session: whether the transition is enabled, and whether the init step is done
+type StandInSessions = Mutex<BTreeMap<u64, (bool, bool)>>;
+
+impl StandInServer {
+    pub fn start(decide: fn(&str) -> Result<bool, String>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let address = listener.local_addr()?;
+        let modules = Arc::new(Mutex::new(Vec::new()));
+        let sessions: Arc<StandInSessions> = Arc::new(Mutex::new(BTreeMap::new()));
+        let loaded = modules.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sessions = sessions.clone();
+                let loaded = loaded.clone();
+                thread::spawn(move || {
+                    let _ = serve(stream, |method, params| {
+                        stand_in_response(method, params, decide, &sessions, &loaded)
+                    });
+                });
+            }
+        });
+        Ok(Self { address, modules })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The names of the root modules loaded so far
+    pub fn loaded_modules(&self) -> Vec<String> {
+        self.modules.lock().unwrap().clone()
+    }
+}
+
+fn stand_in_response(
+    method: &str,
+    params: &Value,
+    decide: fn(&str) -> Result<bool, String>,
+    sessions: &StandInSessions,
+    loaded: &Mutex<Vec<String>>,
+) -> Result<Value, String> {
+    static SESSION_ID: AtomicU64 = AtomicU64::new(0);
+    let session = || {
+        params["sessionId"]
+            .as_str()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(|| "Missing sessionId".to_string())
+    };
+    match method {
+        "loadSpec" => {
+            let sources = params["sources"]
+                .as_array()
+                .ok_or_else(|| "Missing sources".to_string())?
+                .iter()
+                .map(|source| {
+                    source
+                        .as_str()
+                        .and_then(|s| BASE64.decode(s).ok())
+                        .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+                        .ok_or_else(|| "Malformed source".to_string())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let name = sources
+                .first()
+                .ok_or_else(|| "No sources".to_string())?
+                .split("MODULE ")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default();
+            loaded.lock().unwrap().push(name.to_string());
+            let enabled = decide(&sources.join("\n"))?;
+            let id = SESSION_ID.fetch_add(1, Ordering::Relaxed);
+            sessions.lock().unwrap().insert(id, (enabled, false));
+            Ok(json!({
+                "sessionId": id.to_string(),
+                "snapshotId": 0,
+                "specParameters": {
+                    "initTransitions": [{"index": 0, "labels": []}],
+                    "nextTransitions": [{"index": 0, "labels": []}],
+                    "stateInvariants": [],
+                    "actionInvariants": [],
+                },
+            }))
+        }
+        "assumeTransition" => {
+            let id = session()?;
+            let (enabled, after_init) = *sessions
+                .lock()
+                .unwrap()
+                .get(&id)
+                .ok_or_else(|| format!("Unknown session {}", id))?;
+            // The init transition is always enabled; only the step decides
+            let status = if enabled || !after_init {
+                "ENABLED"
+            } else {
+                "DISABLED"
+            };
+            Ok(json!({
+                "sessionId": id.to_string(),
+                "snapshotId": 1,
+                "transitionId": params["transitionId"],
+                "status": status,
+            }))
+        }
+        "nextStep" => {
+            let id = session()?;
+            if let Some((_, after_init)) = sessions.lock().unwrap().get_mut(&id) {
+                *after_init = true;
+            }
+            Ok(json!({"sessionId": id.to_string(), "snapshotId": 1, "newStepNo": 1}))
+        }
+        "rollback" => {
+            Ok(json!({"sessionId": session()?.to_string(), "snapshotId": params["snapshotId"]}))
+        }
+        "disposeSpec" => {
+            let id = session()?;
+            sessions.lock().unwrap().remove(&id);
+            Ok(json!({ "sessionId": id.to_string() }))
+        }
+        other => Err(format!("Unknown method {}", other)),
+    }
+}
+
+/// Answers a single JSON-RPC request on the stream
+fn serve(
+    mut stream: TcpStream,
+    respond: impl Fn(&str, &Value) -> Result<Value, String>,
+) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let body = loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|l| {
+                    l.to_ascii_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|n| n.trim().parse::<usize>().unwrap_or(0))
+                })
+                .unwrap_or(0);
+            if body.len() >= length {
+                break body.to_string();
+            }
+        }
+    };
+    let request: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+    let response = match respond(
+        request["method"].as_str().unwrap_or_default(),
+        &request["params"],
+    ) {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+        Err(message) => json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": {"code": -32000, "message": message},
+        }),
+    }
+    .to_string();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{check_tla_code_link_raw_with, SourceLocation};
+    use crate::test_helpers::test_dir;
+    use std::collections::HashMap;
+
+    fn decide(module: &str) -> Result<bool, String> {
+        if module.contains("BROKEN") {
+            return Err(
+                "***Parse Error***\nEncountered \"==\" at line 3, column 1 in module Counter"
+                    .to_string(),
+            );
+        }
+        Ok(module.contains("/\\ cnt' = 5"))
+    }
+
+    #[test]
+    fn maps_server_responses() {
+        let dir = test_dir("server");
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nVARIABLE cnt\nNext == cnt' = cnt + 1\n====\n",
+        )
+        .unwrap();
+        let broken = dir.join("Broken.tla");
+        fs::write(&broken, "---- MODULE Broken ----\nBROKEN\n====\n").unwrap();
+        let stand_in = StandInServer::start(decide).unwrap();
+        let server = ApalacheServer::connect(stand_in.address());
+        let check = |module: &Path, post: u64| {
+            check_tla_code_link_raw_with(
+                &server,
+                module,
+                "Next".to_string(),
+                vec![],
+                HashMap::from([("cnt".to_string(), "4".to_string())]),
+                HashMap::from([("cnt".to_string(), post.to_string())]),
+                HashMap::new(),
+            )
+        };
+
+        let passed = check(&module, 5);
+        let deadlocked = check(&module, 6);
+        let unparseable = check(&broken, 5);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(passed.is_ok(), "{:?}", passed);
+        match deadlocked {
+            Err(ApalacheError::CheckFailed(failure)) => {
+                assert_eq!(failure.kind, FailureKind::Deadlock)
+            }
+            other => panic!("Expected a deadlock, got {:?}", other),
+        }
+        match unparseable {
+            Err(ApalacheError::CheckFailed(failure)) => assert_eq!(
+                failure.kind,
+                FailureKind::ParseError {
+                    location: Some(SourceLocation {
+                        line: 3,
+                        column: 1,
+                        module: Some("Counter".to_string()),
+                    })
+                }
+            ),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+        assert_eq!(stand_in.loaded_modules().len(), 3);
+        assert!(stand_in
+            .loaded_modules()
+            .iter()
+            .all(|name| name.starts_with("Code_Link_")));
+        assert!(matches!(
+            ApalacheServer::connect(SocketAddr::from(([127, 0, 0, 1], 1))).check(
+                &module,
+                &TransitionCheck {
+                    init_predicate: "Init".to_string(),
+                    next_predicate: "Next".to_string(),
+                    post_state: HashMap::new(),
+                    constants: HashMap::new(),
+                    length: 1,
+                    constant_init: None,
+                }
+            ),
+            Err(ApalacheError::SetupError(_))
+        ));
+    }
+
+    #[test]
+    fn instantiates_specs_with_declared_constants() {
+        let dir = test_dir("server_constants");
+        let module = dir.join("Bounded.tla");
+        fs::write(
+            &module,
+            "---- MODULE Bounded ----\nEXTENDS Naturals\nCONSTANTS MAX, STEP\nVARIABLE cnt\nNext == cnt < MAX /\\ cnt' = cnt + STEP\n====\n",
+        )
+        .unwrap();
+        // Only accepts specs whose recorded constant is substituted, and that don't
+        // constrain it in a definition
+        let stand_in = StandInServer::start(|modules| {
+            Ok(
+                modules.contains("INSTANCE Bounded WITH MAX <- 5, STEP <- 1")
+                    && modules.contains("EXTENDS Naturals\nVARIABLES cnt\n")
+                    && modules.contains("/\\ cnt' = 5")
+                    && !modules.contains("Check_Code_Link_CInit"),
+            )
+        })
+        .unwrap();
+        let server = ApalacheServer::connect(stand_in.address());
+
+        let result = check_tla_code_link_raw_with(
+            &server,
+            &module,
+            "Next".to_string(),
+            vec![],
+            HashMap::from([("cnt".to_string(), "4".to_string())]),
+            HashMap::from([("cnt".to_string(), "5".to_string())]),
+            HashMap::from([
+                ("MAX".to_string(), "5".to_string()),
+                ("STEP".to_string(), "1".to_string()),
+            ]),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok(), "{:?}", result);
+        let loaded = stand_in.loaded_modules();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].starts_with("Code_Link_Bounded_") && loaded[0].ends_with("_Instance"));
+    }
+}
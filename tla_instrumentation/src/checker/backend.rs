@@ -0,0 +1,526 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use super::bundle::shell_command_line;
+use super::process::run_model_checker;
+use super::tla_module::declared_constants;
+use super::{ApalacheError, CheckerConfig, ProcessLimits};
+
+/// What a model checker has to establish about a generated module: that `length` steps
+/// of the next predicate lead from the (single) state described by the init predicate to
+/// some state. The final state is already part of the next predicate; it is repeated here
+/// as the post state for backends that need to refer to it separately.
+#[derive(Clone, Debug)]
+pub struct TransitionCheck {
+    pub init_predicate: String,
+    pub next_predicate: String,
+    pub post_state: HashMap<String, String>,
+    pub constants: HashMap<String, String>,
+    pub length: usize,
+    /// The predicate fixing the values of the constants that the spec declares, if any
+    pub constant_init: Option<String>,
+}
+
+/// A model checker that can decide whether a single transition is possible
+pub trait ModelChecker: Sync {
+    /// Additional definitions to append to the generated module. The original module
+    /// is passed for backends that need to inspect its declarations.
+    fn extra_definitions(
+        &self,
+        _tla_module: &Path,
+        _check: &TransitionCheck,
+    ) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Runs the check on the generated module. A transition that isn't possible must be
+    /// reported as a `FailureKind::Deadlock`.
+    fn check(&self, generated_module: &Path, check: &TransitionCheck) -> Result<(), ApalacheError>;
+
+    /// Identifies the model checker and its version, e.g., for caching results
+    fn version(&self) -> String;
+
+    /// The shell command line that runs the check on the generated module from the
+    /// module's directory, for reproducing failures. `None` if the backend doesn't run a
+    /// process of its own.
+    fn command_line(&self, _generated_module: &Path, _check: &TransitionCheck) -> Option<String> {
+        None
+    }
+}
+
+/// The Apalache versions, by binary, so that we only ask each binary once
+static APALACHE_VERSIONS: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+/// Combines the check's constant values with the configured constraint on the constants
+const COMBINED_CONSTANT_INIT_NAME: &str = "Check_Code_Link_Config_CInit";
+
+#[derive(Clone, Debug)]
+pub struct Apalache {
+    pub config: CheckerConfig,
+}
+
+impl Apalache {
+    pub fn new(binary: &Path) -> Self {
+        Self::from_config(CheckerConfig::new(binary))
+    }
+
+    pub fn from_config(config: CheckerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn with_limits(mut self, limits: ProcessLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    fn constant_init(&self, check: &TransitionCheck) -> Option<String> {
+        match (&check.constant_init, &self.config.constant_init) {
+            (Some(_), Some(_)) => Some(COMBINED_CONSTANT_INIT_NAME.to_string()),
+            (cinit, configured) => cinit.clone().or(configured.clone()),
+        }
+    }
+
+    fn command(&self, generated_module: &Path, check: &TransitionCheck) -> Command {
+        let config = &self.config;
+        // Resolve a relative binary path before changing the current directory
+        let binary = fs::canonicalize(&config.binary).unwrap_or(config.binary.clone());
+        let mut cmd = Command::new(binary);
+        // Apalache writes its output to `_apalache-out` in the current directory
+        if let Some(dir) = generated_module.parent() {
+            cmd.current_dir(dir);
+        }
+        cmd.arg("check")
+            .arg(format!("--init={}", check.init_predicate))
+            .arg(format!("--next={}", check.next_predicate))
+            .arg(format!("--length={}", check.length))
+            .args(self.constant_init(check).map(|c| format!("--cinit={}", c)))
+            .args(config.check_args())
+            .arg(generated_module)
+            .envs(&config.env);
+        // The Apalache launcher script passes JVM_ARGS on to the JVM
+        let jvm_args = config.jvm_args();
+        if !jvm_args.is_empty() {
+            let inherited = config
+                .env
+                .get("JVM_ARGS")
+                .cloned()
+                .or(std::env::var("JVM_ARGS").ok())
+                .unwrap_or_default();
+            cmd.env(
+                "JVM_ARGS",
+                format!("{} {}", inherited, jvm_args.join(" ")).trim(),
+            );
+        }
+        cmd
+    }
+}
+
+impl ModelChecker for Apalache {
+    fn extra_definitions(
+        &self,
+        _tla_module: &Path,
+        check: &TransitionCheck,
+    ) -> Result<Vec<String>, String> {
+        Ok(match (&check.constant_init, &self.config.constant_init) {
+            (Some(cinit), Some(configured)) => vec![format!(
+                "{} ==\n  /\\ {}\n  /\\ {}",
+                COMBINED_CONSTANT_INIT_NAME, cinit, configured
+            )],
+            _ => Vec::new(),
+        })
+    }
+
+    /* Check whether Apalache complains about deadlocks with traces of the given length */
+    fn check(&self, generated_module: &Path, check: &TransitionCheck) -> Result<(), ApalacheError> {
+        run_model_checker(
+            self.command(generated_module, check),
+            generated_module,
+            &self.config.limits,
+        )
+    }
+
+    /// The version of the binary, plus the options that can change the outcome of checks
+    fn version(&self) -> String {
+        let mut versions = APALACHE_VERSIONS.lock().unwrap();
+        let version = versions
+            .entry(self.config.binary.clone())
+            .or_insert_with(|| {
+                Command::new(&self.config.binary)
+                    .arg("version")
+                    .output()
+                    .map(|out| format!("apalache {}", String::from_utf8_lossy(&out.stdout).trim()))
+                    .unwrap_or_default()
+            })
+            .clone();
+        std::iter::once(version)
+            .chain(
+                self.config
+                    .constant_init
+                    .iter()
+                    .map(|c| format!("--cinit={}", c)),
+            )
+            .chain(self.config.check_args())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn command_line(&self, generated_module: &Path, check: &TransitionCheck) -> Option<String> {
+        Some(shell_command_line(&self.command(generated_module, check)))
+    }
+}
+
+const TLC_POST_PREDICATE_NAME: &str = "Check_Code_Link_Post";
+const TLC_INIT_PREDICATE_NAME: &str = "Check_Code_Link_TLC_Init";
+/// Distinct from the step counter of multi-step checks, which TLC checks as well
+const TLC_STEP_VARIABLE: &str = "Check_Code_Link_TLC_Step";
+const TLC_NEXT_PREDICATE_NAME: &str = "Check_Code_Link_TLC_Next";
+const TLC_CONSTANT_PREFIX: &str = "Code_Link_Const_";
+
+/// Runs TLC (`tlc2.TLC` from `tla2tools.jar`).
+///
+/// TLC explores behaviours of unbounded length, so a plain deadlock check would also
+/// fail after a successful transition. The generated next predicate therefore allows
+/// stuttering once the post state is reached, but only after the check's `length` steps
+/// of the transition, which an extra variable counts. Otherwise, a post state equal to
+/// the pre state would let TLC stutter right away: TLC reports a deadlock exactly if the
+/// steps aren't possible. TLC has no counterpart to Apalache's `--cinit`, so the
+/// constants that the spec declares (with `CONSTANT(S)`) are overridden in the generated
+/// `.cfg` file instead.
+#[derive(Clone, Debug)]
+pub struct Tlc {
+    pub java: PathBuf,
+    pub tla2tools: PathBuf,
+    pub limits: ProcessLimits,
+}
+
+impl Tlc {
+    pub fn new(tla2tools: &Path) -> Self {
+        Self {
+            java: PathBuf::from("java"),
+            tla2tools: tla2tools.to_path_buf(),
+            limits: ProcessLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: ProcessLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The overridden constants are exactly those that got an override definition
+    fn config(generated_module: &Path, check: &TransitionCheck) -> Result<String, String> {
+        let text = fs::read_to_string(generated_module).map_err(|e| {
+            format!(
+                "Couldn't read from module {}: {}",
+                generated_module.display(),
+                e
+            )
+        })?;
+        let overridden: BTreeMap<_, _> = check
+            .constants
+            .keys()
+            .filter(|name| text.contains(&format!("{}{} ==", TLC_CONSTANT_PREFIX, name)))
+            .map(|name| (name.clone(), format!("{}{}", TLC_CONSTANT_PREFIX, name)))
+            .collect();
+        Ok(tlc_config(&overridden))
+    }
+
+    /// Runs TLC from the module's directory, with the configuration file next to the module
+    fn command(&self, generated_module: &Path) -> Result<Command, String> {
+        let file_name = generated_module
+            .file_name()
+            .ok_or_else(|| format!("Not a file: {}", generated_module.display()))?;
+        let mut cmd = Command::new(&self.java);
+        if let Some(dir) = generated_module
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+        {
+            cmd.current_dir(dir);
+        }
+        cmd.args(self.limits.jvm_heap_option())
+            .arg("-cp")
+            .arg(&self.tla2tools)
+            .arg("tlc2.TLC")
+            .arg("-config")
+            .arg(Path::new(file_name).with_extension("cfg"))
+            .arg("-workers")
+            .arg("1")
+            .arg("-metadir")
+            .arg("tlc_states")
+            .arg(file_name);
+        Ok(cmd)
+    }
+}
+
+fn tlc_config(overrides: &BTreeMap<String, String>) -> String {
+    let mut config = format!(
+        "INIT {}\nNEXT {}\n",
+        TLC_INIT_PREDICATE_NAME, TLC_NEXT_PREDICATE_NAME
+    );
+    for (name, definition) in overrides {
+        config.push_str(&format!("CONSTANT {} <- {}\n", name, definition));
+    }
+    config
+}
+
+impl ModelChecker for Tlc {
+    fn extra_definitions(
+        &self,
+        tla_module: &Path,
+        check: &TransitionCheck,
+    ) -> Result<Vec<String>, String> {
+        let sorted_post_state: BTreeMap<_, _> = check.post_state.iter().collect();
+        let post_constraint = if sorted_post_state.is_empty() {
+            "  TRUE".to_string()
+        } else {
+            sorted_post_state
+                .iter()
+                .map(|(k, v)| format!("  /\\ {} = {}", k, v))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let mut variables: Vec<&str> = sorted_post_state
+            .keys()
+            .map(|k| k.as_str())
+            .filter(|k| !k.is_empty() && *k != TLC_STEP_VARIABLE)
+            .collect();
+        variables.push(TLC_STEP_VARIABLE);
+        let mut definitions = vec![
+            format!("VARIABLE {}", TLC_STEP_VARIABLE),
+            format!("{} ==\n{}", TLC_POST_PREDICATE_NAME, post_constraint),
+            format!(
+                "{} ==\n  /\\ {}\n  /\\ {} = 0",
+                TLC_INIT_PREDICATE_NAME, check.init_predicate, TLC_STEP_VARIABLE
+            ),
+            format!(
+                "{next} ==\n  \\/ {step} < {length} /\\ {} /\\ {step}' = {step} + 1\n  \\/ {} /\\ {step} = {length} /\\ UNCHANGED <<{}>>",
+                check.next_predicate,
+                TLC_POST_PREDICATE_NAME,
+                variables.join(", "),
+                length = check.length,
+                next = TLC_NEXT_PREDICATE_NAME,
+                step = TLC_STEP_VARIABLE,
+            ),
+        ];
+        let declared = declared_constants(tla_module)?;
+        let sorted_constants: BTreeMap<_, _> = check.constants.iter().collect();
+        for (name, value) in sorted_constants {
+            if declared.contains(name) {
+                definitions.push(format!("{}{} == {}", TLC_CONSTANT_PREFIX, name, value));
+            }
+        }
+        Ok(definitions)
+    }
+
+    fn check(&self, generated_module: &Path, check: &TransitionCheck) -> Result<(), ApalacheError> {
+        let config = Self::config(generated_module, check).map_err(ApalacheError::SetupError)?;
+        fs::write(generated_module.with_extension("cfg"), config)
+            .map_err(|e| ApalacheError::SetupError(e.to_string()))?;
+        let cmd = self
+            .command(generated_module)
+            .map_err(ApalacheError::SetupError)?;
+        run_model_checker(cmd, generated_module, &self.limits)
+    }
+
+    fn command_line(&self, generated_module: &Path, _check: &TransitionCheck) -> Option<String> {
+        self.command(generated_module)
+            .ok()
+            .map(|cmd| shell_command_line(&cmd))
+    }
+
+    fn version(&self) -> String {
+        // Asking TLC for its version needs a JVM start, so use the jar's identity instead
+        let metadata = fs::metadata(&self.tla2tools)
+            .map(|m| format!("{} {:?}", m.len(), m.modified().ok()))
+            .unwrap_or_default();
+        format!("tlc {} {}", self.tla2tools.display(), metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+
+    #[test]
+    fn tlc_definitions_and_config() {
+        let dir = test_dir("tlc");
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nEXTENDS Naturals\nCONSTANT MAX\nVARIABLES cnt, done\n====\n",
+        )
+        .unwrap();
+        let check = TransitionCheck {
+            init_predicate: "Check_Code_Link_Init".to_string(),
+            next_predicate: "Check_Code_Link_Next".to_string(),
+            post_state: HashMap::from([
+                ("cnt".to_string(), "2".to_string()),
+                ("done".to_string(), "TRUE".to_string()),
+            ]),
+            constants: HashMap::from([
+                ("MAX".to_string(), "3".to_string()),
+                ("UNDECLARED".to_string(), "4".to_string()),
+            ]),
+            length: 1,
+            constant_init: None,
+        };
+        let definitions = Tlc::new(Path::new("tla2tools.jar"))
+            .extra_definitions(&module, &check)
+            .unwrap();
+        assert_eq!(
+            definitions,
+            vec![
+                "VARIABLE Check_Code_Link_TLC_Step".to_string(),
+                "Check_Code_Link_Post ==\n  /\\ cnt = 2\n  /\\ done = TRUE".to_string(),
+                "Check_Code_Link_TLC_Init ==\n  /\\ Check_Code_Link_Init\n  /\\ Check_Code_Link_TLC_Step = 0".to_string(),
+                "Check_Code_Link_TLC_Next ==\n  \\/ Check_Code_Link_TLC_Step < 1 /\\ Check_Code_Link_Next /\\ Check_Code_Link_TLC_Step' = Check_Code_Link_TLC_Step + 1\n  \\/ Check_Code_Link_Post /\\ Check_Code_Link_TLC_Step = 1 /\\ UNCHANGED <<cnt, done, Check_Code_Link_TLC_Step>>".to_string(),
+                "Code_Link_Const_MAX == 3".to_string(),
+            ]
+        );
+        let next_for_post_state = |post_state: HashMap<String, String>| {
+            Tlc::new(Path::new("tla2tools.jar"))
+                .extra_definitions(
+                    &module,
+                    &TransitionCheck {
+                        post_state,
+                        ..check.clone()
+                    },
+                )
+                .unwrap()
+        };
+        // Without recorded variables, only the step variable stays unchanged
+        let empty = next_for_post_state(HashMap::new());
+        assert_eq!(empty[1], "Check_Code_Link_Post ==\n  TRUE");
+        assert!(empty[3].ends_with(" /\\ UNCHANGED <<Check_Code_Link_TLC_Step>>"));
+        // The step variable is listed once, even if the post state already contains it
+        let with_step = next_for_post_state(HashMap::from([
+            ("cnt".to_string(), "2".to_string()),
+            (TLC_STEP_VARIABLE.to_string(), "1".to_string()),
+        ]));
+        assert!(with_step[3].ends_with(" /\\ UNCHANGED <<cnt, Check_Code_Link_TLC_Step>>"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            tlc_config(&BTreeMap::from([(
+                "MAX".to_string(),
+                "Code_Link_Const_MAX".to_string()
+            )])),
+            "INIT Check_Code_Link_TLC_Init\nNEXT Check_Code_Link_TLC_Next\nCONSTANT MAX <- Code_Link_Const_MAX\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tlc_only_stutters_after_the_transition() {
+        use crate::checker::check_tla_code_link_raw_with;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("tlc_stutter");
+        let captured = dir.join("captured");
+        fs::create_dir_all(&captured).unwrap();
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nEXTENDS Naturals\nVARIABLE cnt\nNext == cnt' = cnt + 1\n====\n",
+        )
+        .unwrap();
+        // Stands in for the JVM: keeps the generated files, which TLC would check
+        let java = dir.join("java");
+        fs::write(
+            &java,
+            format!(
+                "#!/bin/sh\ncp ./Code_Link_*.tla ./*.cfg '{}'\n",
+                captured.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&java, fs::Permissions::from_mode(0o755)).unwrap();
+        let tlc = Tlc {
+            java,
+            ..Tlc::new(Path::new("tla2tools.jar"))
+        };
+        let state = HashMap::from([("cnt".to_string(), "1".to_string())]);
+
+        let result = check_tla_code_link_raw_with(
+            &tlc,
+            &module,
+            "Next".to_string(),
+            vec![],
+            state.clone(),
+            state,
+            HashMap::new(),
+        );
+        let read = |extension: &str| {
+            fs::read_dir(&captured)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| path.extension().is_some_and(|e| e == extension))
+                .map(|path| fs::read_to_string(path).unwrap())
+                .unwrap()
+        };
+        let (generated, config) = (read("tla"), read("cfg"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        assert!(
+            config.starts_with("INIT Check_Code_Link_TLC_Init\nNEXT Check_Code_Link_TLC_Next\n")
+        );
+        // The initial state can't stutter, so TLC has to take a step of the transition
+        // (which is impossible here, as it increments the counter) to avoid a deadlock
+        assert!(generated.contains("/\\ Check_Code_Link_Init\n  /\\ Check_Code_Link_TLC_Step = 0"));
+        assert!(generated.contains(
+            "\\/ Check_Code_Link_Post /\\ Check_Code_Link_TLC_Step = 1 /\\ UNCHANGED <<cnt, Check_Code_Link_TLC_Step>>"
+        ));
+        assert!(generated.contains(
+            "\\/ Check_Code_Link_TLC_Step < 1 /\\ Check_Code_Link_Next /\\ Check_Code_Link_TLC_Step' = Check_Code_Link_TLC_Step + 1"
+        ));
+    }
+
+    #[test]
+    fn apalache_command_line() {
+        let apalache = Apalache::from_config(
+            CheckerConfig::new(Path::new("/nonexistent/apalache-mc"))
+                .with_constant_init("SmallNetwork")
+                .with_smt_encoding("arrays")
+                .with_env("TMPDIR", "/scratch")
+                .with_limits(ProcessLimits::new().with_jvm_heap("2g")),
+        );
+        let check = TransitionCheck {
+            init_predicate: "Init".to_string(),
+            next_predicate: "Next".to_string(),
+            post_state: HashMap::new(),
+            constants: HashMap::new(),
+            length: 1,
+            constant_init: Some("CInit".to_string()),
+        };
+        let module = Path::new("/work/Generated.tla");
+        let cmd = apalache.command(module, &check);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            vec![
+                "check",
+                "--init=Init",
+                "--next=Next",
+                "--length=1",
+                "--cinit=Check_Code_Link_Config_CInit",
+                "--smt-encoding=arrays",
+                "/work/Generated.tla",
+            ]
+        );
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/work")));
+        let envs: BTreeMap<_, _> = cmd
+            .get_envs()
+            .map(|(k, v)| (k.to_string_lossy(), v.unwrap().to_string_lossy()))
+            .collect();
+        assert_eq!(envs["TMPDIR"], "/scratch");
+        assert!(envs["JVM_ARGS"].ends_with("-Xmx2g"));
+        assert_eq!(
+            apalache.extra_definitions(module, &check).unwrap(),
+            vec!["Check_Code_Link_Config_CInit ==\n  /\\ CInit\n  /\\ SmallNetwork"]
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{
+    check_tla_code_link_cached, check_tla_code_link_with, ModelChecker, PredicateDescription,
+    ResultCache, TlaCheckError,
+};
+use crate::{Canonicalization, ResolvedStatePair, TlaConstantAssignment, UpdateTrace};
+
+/// Identifies a recorded state pair: the index of its trace in the batch and its
+/// index within that trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PairLocation {
+    pub trace_index: usize,
+    pub pair_index: usize,
+}
+
+pub struct BatchOptions<'a> {
+    /// The maximum number of model checker processes running at the same time
+    pub workers: usize,
+    /// Used to detect duplicate pairs, which are only checked once
+    pub canonicalization: Canonicalization,
+    /// Called after every finished check with the number of finished checks and
+    /// the total number of checks
+    pub progress: Option<&'a (dyn Fn(usize, usize) + Sync)>,
+    /// If set, checks that already passed are skipped
+    pub cache: Option<&'a ResultCache>,
+}
+
+impl Default for BatchOptions<'_> {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            canonicalization: Canonicalization::new(),
+            progress: None,
+            cache: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchFailure {
+    /// The pair that was checked
+    pub location: PairLocation,
+    /// Other pairs that are duplicates of the checked one, and thus fail as well
+    pub duplicates: Vec<PairLocation>,
+    pub error: TlaCheckError,
+}
+
+/// What happened to a recorded pair in a batch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairStatus {
+    /// Checked, which took the given time
+    Checked(Duration),
+    /// Not checked, as the step doesn't change any variable
+    Stuttering,
+    /// Not checked, as it duplicates the checked pair at the location, and shares its result
+    Duplicate(PairLocation),
+}
+
+#[derive(Debug)]
+pub struct BatchReport {
+    /// The number of recorded pairs across all traces
+    pub total_pairs: usize,
+    /// The number of model checker runs
+    pub checked: usize,
+    /// The number of pairs that weren't checked as they were stuttering steps or duplicates
+    pub skipped: usize,
+    pub failures: Vec<BatchFailure>,
+    /// The status of every recorded pair
+    pub statuses: BTreeMap<PairLocation, PairStatus>,
+    /// The wall-clock time of the whole batch
+    pub elapsed: Duration,
+}
+
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+struct Job {
+    location: PairLocation,
+    duplicates: Vec<PairLocation>,
+    pair: ResolvedStatePair,
+    constants: TlaConstantAssignment,
+}
+
+/// The jobs to run, and the statuses of the pairs that aren't checked
+fn collect_jobs(
+    traces: &[UpdateTrace],
+    canonicalization: &Canonicalization,
+) -> (Vec<Job>, BTreeMap<PairLocation, PairStatus>) {
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut statuses = BTreeMap::new();
+    let mut job_indices: BTreeMap<(ResolvedStatePair, TlaConstantAssignment), usize> =
+        BTreeMap::new();
+    for (trace_index, trace) in traces.iter().enumerate() {
+        let normalized = trace.normalize(canonicalization);
+        let location = |pair_index| PairLocation {
+            trace_index,
+            pair_index,
+        };
+        let mut jobs_by_pair = BTreeMap::new();
+        for (pair, pair_index) in normalized
+            .state_pairs
+            .into_iter()
+            .zip(normalized.original_indices)
+        {
+            let key = (
+                canonicalization.canonicalize(&pair),
+                trace.constants.clone(),
+            );
+            let job_index = match job_indices.get(&key) {
+                Some(&job_index) => {
+                    jobs[job_index].duplicates.push(location(pair_index));
+                    job_index
+                }
+                None => {
+                    jobs.push(Job {
+                        location: location(pair_index),
+                        duplicates: Vec::new(),
+                        pair,
+                        constants: trace.constants.clone(),
+                    });
+                    job_indices.insert(key, jobs.len() - 1);
+                    jobs.len() - 1
+                }
+            };
+            jobs_by_pair.insert(pair_index, job_index);
+        }
+        for (duplicate, original) in normalized.duplicates {
+            jobs[jobs_by_pair[&original]]
+                .duplicates
+                .push(location(duplicate));
+        }
+        for pair_index in normalized.stuttering {
+            statuses.insert(location(pair_index), PairStatus::Stuttering);
+        }
+    }
+    for job in &jobs {
+        for duplicate in &job.duplicates {
+            statuses.insert(*duplicate, PairStatus::Duplicate(job.location));
+        }
+    }
+    (jobs, statuses)
+}
+
+/// Checks all state pairs of the given traces against the predicate, running up to
+/// `options.workers` model checker processes in parallel. Stuttering steps are skipped,
+/// and duplicate pairs (with the same constants) are checked only once, also across
+/// traces. All failures are collected, rather than stopping at the first one.
+pub fn check_traces(
+    checker: &dyn ModelChecker,
+    predicate: &PredicateDescription,
+    traces: &[UpdateTrace],
+    options: &BatchOptions,
+) -> BatchReport {
+    let total_pairs = traces.iter().map(|t| t.state_pairs.len()).sum();
+    let started = Instant::now();
+    let (jobs, mut statuses) = collect_jobs(traces, &options.canonicalization);
+    let next_job = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let errors: Mutex<Vec<(usize, TlaCheckError)>> = Mutex::new(Vec::new());
+    let durations: Mutex<Vec<(PairLocation, Duration)>> = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..options.workers.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let job_index = next_job.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = jobs.get(job_index) else {
+                    break;
+                };
+                let job_started = Instant::now();
+                let result = match options.cache {
+                    Some(cache) => check_tla_code_link_cached(
+                        checker,
+                        cache,
+                        predicate.clone(),
+                        job.pair.clone(),
+                        job.constants.clone(),
+                    ),
+                    None => check_tla_code_link_with(
+                        checker,
+                        predicate.clone(),
+                        job.pair.clone(),
+                        job.constants.clone(),
+                    ),
+                };
+                durations
+                    .lock()
+                    .unwrap()
+                    .push((job.location, job_started.elapsed()));
+                if let Err(e) = result {
+                    errors.lock().unwrap().push((job_index, e));
+                }
+                let done = finished.fetch_add(1, Ordering::SeqCst) + 1;
+                if let Some(progress) = options.progress {
+                    progress(done, jobs.len());
+                }
+            });
+        }
+    });
+
+    for (location, duration) in durations.into_inner().unwrap() {
+        statuses.insert(location, PairStatus::Checked(duration));
+    }
+    let mut errors = errors.into_inner().unwrap();
+    errors.sort_by_key(|(job_index, _)| *job_index);
+    let checked = jobs.len();
+    let mut jobs: Vec<Option<Job>> = jobs.into_iter().map(Some).collect();
+    let failures = errors
+        .into_iter()
+        .map(|(job_index, error)| {
+            let job = jobs[job_index].take().expect("Job failed twice");
+            BatchFailure {
+                location: job.location,
+                duplicates: job.duplicates,
+                error,
+            }
+        })
+        .collect();
+    BatchReport {
+        total_pairs,
+        checked,
+        skipped: total_pairs - checked,
+        failures,
+        statuses,
+        elapsed: started.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{ApalacheError, FailureKind, FakeChecker, Verdict};
+    use crate::test_helpers::{test_dir, update};
+    use crate::{GlobalState, ToTla};
+
+    fn trace(counters: Vec<(u64, u64)>) -> UpdateTrace {
+        let state = |counter: u64| {
+            let mut state = GlobalState::new();
+            state.add("counter", counter.to_tla_value());
+            state
+        };
+        crate::test_helpers::trace(
+            update("can", "pid"),
+            counters
+                .into_iter()
+                .map(|(start, end)| ResolvedStatePair {
+                    start: state(start),
+                    end: state(end),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn aggregates_failures_of_unique_pairs() {
+        let dir = test_dir("batch");
+        let module = dir.join("Counter.tla");
+        std::fs::write(
+            &module,
+            "---- MODULE Counter ----\nVARIABLE counter\n====\n",
+        )
+        .unwrap();
+        let predicate = PredicateDescription {
+            tla_module: module,
+            transition_predicate: "Next".to_string(),
+            predicate_parameters: vec![],
+        };
+        let traces = vec![
+            trace(vec![(0, 1), (1, 1), (0, 1), (1, 2)]),
+            trace(vec![(1, 2), (2, 3)]),
+        ];
+        let finished = Mutex::new(Vec::new());
+        let progress = |done: usize, total: usize| finished.lock().unwrap().push((done, total));
+        let options = BatchOptions {
+            workers: 2,
+            progress: Some(&progress),
+            ..BatchOptions::default()
+        };
+
+        // Only the steps to 2 fail
+        let checker =
+            FakeChecker::new().when_contains("counter' = 2", Verdict::Fail(FailureKind::Deadlock));
+        let report = check_traces(&checker, &predicate, &traces, &options);
+        for bundle in report.failures.iter().filter_map(|f| f.error.bundle()) {
+            let _ = std::fs::remove_dir_all(bundle);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.total_pairs, 6);
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.skipped, 3);
+        assert_eq!(checker.recorded().len(), 3);
+        assert!(!report.is_success());
+        let locations: Vec<_> = report
+            .failures
+            .iter()
+            .map(|f| (f.location, f.duplicates.clone()))
+            .collect();
+        let at = |trace_index, pair_index| PairLocation {
+            trace_index,
+            pair_index,
+        };
+        assert_eq!(locations, vec![(at(0, 3), vec![at(1, 0)])]);
+        assert!(matches!(
+            report.failures[0].error.apalache_error,
+            ApalacheError::CheckFailed(_)
+        ));
+        assert_eq!(report.statuses.len(), 6);
+        assert_eq!(report.statuses[&at(0, 1)], PairStatus::Stuttering);
+        assert_eq!(report.statuses[&at(0, 2)], PairStatus::Duplicate(at(0, 0)));
+        assert_eq!(report.statuses[&at(1, 0)], PairStatus::Duplicate(at(0, 3)));
+        assert!(matches!(report.statuses[&at(0, 0)], PairStatus::Checked(_)));
+        assert!(matches!(report.statuses[&at(1, 1)], PairStatus::Checked(_)));
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort();
+        assert_eq!(finished, vec![(1, 3), (2, 3), (3, 3)]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::{json, Map, Value};
+
+use super::{artifacts_dir, ApalacheError, ModelChecker, TransitionCheck};
+use crate::{GlobalState, TlaConstantAssignment, TlaValue};
+
+/// The file holding the recorded states and constants in the Informal Trace Format
+pub const ITF_FILE_NAME: &str = "states.itf.json";
+/// The file holding the recorded states and constants as TLA+ text
+pub const TLA_STATES_FILE_NAME: &str = "states.tla";
+/// The file holding the model checker's command line
+pub const COMMAND_FILE_NAME: &str = "command.txt";
+/// The script that re-runs the model checker on the bundle
+pub const RERUN_SCRIPT_NAME: &str = "rerun.sh";
+
+/// Quotes a string for the shell
+pub(crate) fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Renders the command as a shell command line, including the environment variables it
+/// sets. The working directory isn't part of it.
+pub(crate) fn shell_command_line(cmd: &Command) -> String {
+    cmd.get_envs()
+        .filter_map(|(name, value)| {
+            value.map(|v| {
+                format!(
+                    "{}={}",
+                    name.to_string_lossy(),
+                    sh_quote(&v.to_string_lossy())
+                )
+            })
+        })
+        .chain(std::iter::once(sh_quote(
+            &cmd.get_program().to_string_lossy(),
+        )))
+        .chain(cmd.get_args().map(|arg| sh_quote(&arg.to_string_lossy())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Encodes a value in the Informal Trace Format (ITF)
+pub fn itf_value(value: &TlaValue) -> Value {
+    match value {
+        TlaValue::Set(elements) => {
+            json!({ "#set": elements.iter().map(itf_value).collect::<Vec<_>>() })
+        }
+        TlaValue::Record(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), itf_value(v)))
+                .collect(),
+        ),
+        TlaValue::Function(map) => json!({
+            "#map": map
+                .iter()
+                .map(|(k, v)| json!([itf_value(k), itf_value(v)]))
+                .collect::<Vec<_>>()
+        }),
+        TlaValue::Seq(elements) => Value::Array(elements.iter().map(itf_value).collect()),
+        TlaValue::Literal(s) => Value::String(s.clone()),
+        TlaValue::Constant(s) => json!({ "#unserializable": s }),
+        TlaValue::Bool(b) => Value::Bool(*b),
+        TlaValue::Int(_) => json!({ "#bigint": value.to_string() }),
+        TlaValue::Variant { tag, value } => json!({ "tag": tag, "value": itf_value(value) }),
+    }
+}
+
+/// The states as an ITF trace. The constants are the trace's parameters, and are part of
+/// every state.
+pub fn itf_trace(states: &[&GlobalState], constants: &TlaConstantAssignment) -> Value {
+    let variables: Vec<&String> = states
+        .first()
+        .map(|state| state.0 .0.keys().collect())
+        .unwrap_or_default();
+    let states: Vec<Value> = states
+        .iter()
+        .enumerate()
+        .map(|(index, state)| {
+            let mut fields = Map::new();
+            fields.insert("#meta".to_string(), json!({ "index": index }));
+            for (name, value) in constants.constants.iter().chain(state.0 .0.iter()) {
+                fields.insert(name.clone(), itf_value(value));
+            }
+            Value::Object(fields)
+        })
+        .collect();
+    json!({
+        "#meta": { "format": "ITF", "source": "tla_instrumentation" },
+        "params": constants.constants.keys().collect::<Vec<_>>(),
+        "vars": variables,
+        "states": states,
+    })
+}
+
+fn tla_text(states: &[&GlobalState], constants: &TlaConstantAssignment) -> String {
+    let mut text = String::new();
+    for (index, state) in states.iter().enumerate() {
+        text.push_str(&format!("\\* State {}\n", index));
+        for (name, value) in &state.0 .0 {
+            text.push_str(&format!("/\\ {} = {}\n", name, value));
+        }
+    }
+    if !constants.constants.is_empty() {
+        text.push_str("\\* Constants\n");
+        for (name, value) in &constants.constants {
+            text.push_str(&format!("/\\ {} = {}\n", name, value));
+        }
+    }
+    text
+}
+
+/// The directory that was kept for the failed check, if any
+pub(crate) fn bundle_dir(error: &ApalacheError) -> Option<PathBuf> {
+    let module = match error {
+        ApalacheError::CheckFailed(failure) => failure.module.as_path(),
+        ApalacheError::Timeout { module, .. } => module,
+        ApalacheError::SetupError(_) | ApalacheError::DeclarationMismatch(_) => return None,
+    };
+    module
+        .parent()
+        .filter(|dir| dir.starts_with(artifacts_dir()) && dir.is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Adds the recorded states and constants to the bundle of a failed check. Writing the
+/// bundle is best effort: a failure to do so shouldn't hide the failure of the check.
+pub(crate) fn complete_bundle(
+    error: &ApalacheError,
+    states: &[&GlobalState],
+    constants: &TlaConstantAssignment,
+) {
+    let Some(dir) = bundle_dir(error) else {
+        return;
+    };
+    let itf = serde_json::to_string_pretty(&itf_trace(states, constants)).unwrap_or_default();
+    let _ = fs::write(dir.join(ITF_FILE_NAME), itf);
+    let _ = fs::write(dir.join(TLA_STATES_FILE_NAME), tla_text(states, constants));
+}
+
+/// Writes the command line, and a script that runs it from the bundle's directory
+pub(crate) fn write_rerun_script(dir: &Path, command_line: &str) -> io::Result<()> {
+    fs::write(dir.join(COMMAND_FILE_NAME), format!("{}\n", command_line))?;
+    let script = dir.join(RERUN_SCRIPT_NAME);
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\ncd \"$(dirname \"$0\")\" || exit 1\n{}\n",
+            command_line
+        ),
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Runs the check on the module in its working directory. If the check fails, the
+/// directory becomes a bundle, so the command line is added to it.
+pub(crate) fn run_check(
+    checker: &dyn ModelChecker,
+    generated_module: &Path,
+    check: &TransitionCheck,
+) -> Result<(), ApalacheError> {
+    let result = checker.check(generated_module, check);
+    if result.is_err() {
+        if let (Some(dir), Some(file_name)) =
+            (generated_module.parent(), generated_module.file_name())
+        {
+            if let Some(command_line) = checker.command_line(Path::new(file_name), check) {
+                let _ = write_rerun_script(dir, &command_line);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ToTla;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn encodes_itf() {
+        let mut start = GlobalState::new();
+        start.add("cnt", 4_u64.to_tla_value());
+        start.add(
+            "pc",
+            BTreeMap::from([("p1".to_string(), "Start".to_string())]).to_tla_value(),
+        );
+        let mut end = start.clone();
+        end.add("cnt", 6_u64.to_tla_value());
+        let constants = TlaConstantAssignment {
+            constants: BTreeMap::from([(
+                "USERS".to_string(),
+                BTreeSet::from(["alice".to_string()]).to_tla_value(),
+            )]),
+        };
+        let itf = itf_trace(&[&start, &end], &constants);
+        assert_eq!(
+            itf["states"][1],
+            json!({
+                "#meta": { "index": 1 },
+                "USERS": { "#set": ["alice"] },
+                "cnt": { "#bigint": "6" },
+                "pc": { "#map": [["p1", "Start"]] },
+            })
+        );
+        assert_eq!(itf["vars"], json!(["cnt", "pc"]));
+        assert_eq!(itf["params"], json!(["USERS"]));
+        assert_eq!(
+            tla_text(&[&start], &constants),
+            "\\* State 0\n/\\ cnt = 4\n/\\ pc = (\"p1\" :> \"Start\")\n\\* Constants\n/\\ USERS = {\"alice\"}\n"
+        );
+    }
+}
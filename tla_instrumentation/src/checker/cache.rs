@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::tla_module::module_closure;
+use super::{
+    check_tla_code_link_with, sha256_hex, ModelChecker, PredicateDescription, TlaCheckError,
+};
+use crate::{ResolvedStatePair, TlaConstantAssignment};
+
+/// Environment variable holding the cache directory
+pub const CACHE_DIR_ENV_VAR: &str = "TLA_CHECK_CACHE_DIR";
+/// Environment variable holding the cache policy: `use`, `bypass` or `refresh`
+pub const CACHE_POLICY_ENV_VAR: &str = "TLA_CHECK_CACHE";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Skip checks that already passed, and record new passes
+    Use,
+    /// Neither read nor write the cache
+    Bypass,
+    /// Re-run all checks, but record new passes
+    Refresh,
+}
+
+/// An on-disk record of the checks that passed. Entries are keyed by a hash of
+/// everything that influences the result: the names and texts of the TLA+ module and
+/// the modules it imports, the predicate and its parameters, the states, the constants
+/// and the version of the model checker.
+#[derive(Clone, Debug)]
+pub struct ResultCache {
+    pub directory: PathBuf,
+    pub policy: CachePolicy,
+}
+
+fn push_field(input: &mut Vec<u8>, field: &str) {
+    // Length-prefix the fields, such that different inputs can't produce the same bytes
+    input.extend(field.len().to_le_bytes());
+    input.extend(field.as_bytes());
+}
+
+fn push_map(input: &mut Vec<u8>, map: &HashMap<String, String>) {
+    let sorted: BTreeMap<_, _> = map.iter().collect();
+    push_field(input, &sorted.len().to_string());
+    for (k, v) in sorted {
+        push_field(input, k);
+        push_field(input, v);
+    }
+}
+
+impl ResultCache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            policy: CachePolicy::Use,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: CachePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Reads the cache configuration from the `TLA_CHECK_CACHE_DIR` and `TLA_CHECK_CACHE`
+    /// environment variables. Returns `None` if no cache directory is set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(directory) = std::env::var(CACHE_DIR_ENV_VAR) else {
+            return Ok(None);
+        };
+        let policy = match std::env::var(CACHE_POLICY_ENV_VAR).as_deref() {
+            Err(_) | Ok("use") => CachePolicy::Use,
+            Ok("bypass") => CachePolicy::Bypass,
+            Ok("refresh") => CachePolicy::Refresh,
+            Ok(other) => {
+                return Err(format!(
+                    "Unknown cache policy {} in {}; expected use, bypass or refresh",
+                    other, CACHE_POLICY_ENV_VAR
+                ))
+            }
+        };
+        Ok(Some(
+            Self::new(PathBuf::from(directory)).with_policy(policy),
+        ))
+    }
+
+    /// Removes all cached results
+    pub fn invalidate(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.directory) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn key(
+        &self,
+        model_checker: &dyn ModelChecker,
+        tla_module: &Path,
+        transition_predicate: &str,
+        predicate_parameters: &[String],
+        pre_state: &HashMap<String, String>,
+        post_state: &HashMap<String, String>,
+        constants: &HashMap<String, String>,
+    ) -> Result<String, String> {
+        let mut input = Vec::new();
+        push_field(&mut input, &model_checker.version());
+        for file in module_closure(tla_module)? {
+            let text = fs::read_to_string(&file)
+                .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+            // All modules are in the spec's directory. Leave the directory out, such that
+            // entries also hit for other checkouts of the same spec.
+            let name = file.file_name().unwrap_or(file.as_os_str());
+            push_field(&mut input, &name.to_string_lossy());
+            push_field(&mut input, &text);
+        }
+        push_field(&mut input, transition_predicate);
+        push_field(&mut input, &predicate_parameters.len().to_string());
+        for parameter in predicate_parameters {
+            push_field(&mut input, parameter);
+        }
+        push_map(&mut input, pre_state);
+        push_map(&mut input, post_state);
+        push_map(&mut input, constants);
+        Ok(sha256_hex(input))
+    }
+
+    fn entry(&self, key: &str) -> PathBuf {
+        self.directory.join(key)
+    }
+
+    pub(crate) fn has_passed(&self, key: &str) -> bool {
+        self.policy == CachePolicy::Use && self.entry(key).exists()
+    }
+
+    pub(crate) fn record_pass(&self, key: &str) {
+        if self.policy == CachePolicy::Bypass {
+            return;
+        }
+        // Failing to record a result only costs a re-run later, so ignore errors
+        let _ = fs::create_dir_all(&self.directory)
+            .and_then(|_| fs::write(self.entry(key), "passed\n"));
+    }
+}
+
+/// Like `check_tla_code_link`, but skips the check if the cache records that the same
+/// check already passed, and records the result if it passes
+pub fn check_tla_code_link_cached(
+    checker: &dyn ModelChecker,
+    cache: &ResultCache,
+    predicate: PredicateDescription,
+    state_pair: ResolvedStatePair,
+    constants: TlaConstantAssignment,
+) -> Result<(), TlaCheckError> {
+    let key = if cache.policy == CachePolicy::Bypass {
+        None
+    } else {
+        // If we can't compute the key, just run the check without the cache
+        predicate
+            .bind_parameters(&state_pair)
+            .and_then(|parameters| {
+                cache.key(
+                    checker,
+                    &predicate.tla_module,
+                    &predicate.transition_predicate,
+                    &parameters,
+                    &state_pair.start.to_map(),
+                    &state_pair.end.to_map(),
+                    &constants.to_map(),
+                )
+            })
+            .ok()
+    };
+    if let Some(key) = &key {
+        if cache.has_passed(key) {
+            return Ok(());
+        }
+    }
+    check_tla_code_link_with(checker, predicate, state_pair, constants)?;
+    if let Some(key) = &key {
+        cache.record_pass(key);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::checker::Apalache;
+    use crate::test_helpers::{no_constants, test_dir};
+    use crate::{GlobalState, ToTla};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn skips_checks_that_passed() {
+        let dir = test_dir("cache");
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nEXTENDS Naturals, CounterBase\nNext == cnt' = cnt + 1\n====\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("CounterBase.tla"),
+            "---- MODULE CounterBase ----\nVARIABLE cnt\n====\n",
+        )
+        .unwrap();
+        // A stand-in for Apalache that accepts everything and logs its invocations
+        let runs = dir.join("runs");
+        let apalache = dir.join("apalache-mc");
+        fs::write(
+            &apalache,
+            format!(
+                "#!/bin/sh\nif [ \"$1\" = version ]; then echo 0.1.0; else echo run >> {}; fi\n",
+                runs.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&apalache, fs::Permissions::from_mode(0o755)).unwrap();
+        let run_count = || {
+            fs::read_to_string(&runs)
+                .map(|s| s.lines().count())
+                .unwrap_or(0)
+        };
+
+        let predicate = PredicateDescription {
+            tla_module: module,
+            transition_predicate: "Next".to_string(),
+            predicate_parameters: vec![],
+        };
+        let state = |cnt: u64| {
+            let mut state = GlobalState::new();
+            state.add("cnt", cnt.to_tla_value());
+            state
+        };
+        let pair = ResolvedStatePair {
+            start: state(1),
+            end: state(2),
+        };
+        let constants = no_constants();
+        let cache = ResultCache::new(dir.join("cache"));
+        let check_pair = |cache: &ResultCache, pair: &ResolvedStatePair| {
+            check_tla_code_link_cached(
+                &Apalache::new(&apalache),
+                cache,
+                predicate.clone(),
+                pair.clone(),
+                constants.clone(),
+            )
+            .expect("Check failed")
+        };
+        let check = |cache: &ResultCache| check_pair(cache, &pair);
+        let key = |module: &Path| {
+            cache
+                .key(
+                    &Apalache::new(&apalache),
+                    module,
+                    "Next",
+                    &[],
+                    &pair.start.to_map(),
+                    &pair.end.to_map(),
+                    &HashMap::new(),
+                )
+                .unwrap()
+        };
+
+        check(&cache);
+        check(&cache);
+        assert_eq!(run_count(), 1);
+        check(&cache.clone().with_policy(CachePolicy::Bypass));
+        assert_eq!(run_count(), 2);
+        // Changing an imported module changes the key
+        let old_key = key(&predicate.tla_module);
+        fs::write(
+            dir.join("CounterBase.tla"),
+            "---- MODULE CounterBase ----\nVARIABLES cnt, other\n====\n",
+        )
+        .unwrap();
+        assert_ne!(key(&predicate.tla_module), old_key);
+        // The states now have to record the new variable as well
+        let mut pair = pair.clone();
+        pair.start.add("other", 0_u64.to_tla_value());
+        pair.end.add("other", 0_u64.to_tla_value());
+        check_pair(&cache, &pair);
+        check_pair(&cache, &pair);
+        assert_eq!(run_count(), 3);
+        cache.invalidate().unwrap();
+        check_pair(&cache, &pair);
+        assert_eq!(run_count(), 4);
+        // Another checkout of the same modules has the same keys
+        let checkout = dir.join("checkout");
+        fs::create_dir_all(&checkout).unwrap();
+        for name in ["Counter.tla", "CounterBase.tla"] {
+            fs::copy(dir.join(name), checkout.join(name)).unwrap();
+        }
+        assert_eq!(
+            key(&checkout.join("Counter.tla")),
+            key(&predicate.tla_module)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::ProcessLimits;
+
+/// Environment variable holding the path of a configuration file
+pub const CONFIG_FILE_ENV_VAR: &str = "TLA_CHECK_CONFIG";
+/// Environment variable holding the Apalache binary
+pub const APALACHE_BINARY_ENV_VAR: &str = "TLA_CHECK_APALACHE";
+/// Environment variable holding additional JVM options, separated by spaces
+pub const JVM_OPTIONS_ENV_VAR: &str = "TLA_CHECK_JVM_OPTIONS";
+/// Environment variable holding the Apalache features to enable, separated by commas
+pub const FEATURES_ENV_VAR: &str = "TLA_CHECK_FEATURES";
+/// Environment variable holding the SMT encoding
+pub const SMT_ENCODING_ENV_VAR: &str = "TLA_CHECK_SMT_ENCODING";
+/// Environment variable holding the Apalache output directory
+pub const OUT_DIR_ENV_VAR: &str = "TLA_CHECK_OUT_DIR";
+/// Environment variable holding the verbosity: `normal` or `debug`
+pub const VERBOSITY_ENV_VAR: &str = "TLA_CHECK_VERBOSITY";
+/// Environment variable holding the timeout for a single check, in seconds
+pub const TIMEOUT_ENV_VAR: &str = "TLA_CHECK_TIMEOUT";
+
+const DEFAULT_APALACHE_BINARY: &str = "apalache-mc";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    #[default]
+    Normal,
+    /// Runs Apalache with `--debug`, which writes a detailed log and the intermediate
+    /// outputs to the output directory
+    Debug,
+}
+
+/// How to run Apalache. The `--init`, `--next`, `--length` and `--cinit` arguments are
+/// determined by each check; `constant_init` adds a further constraint on the constants
+/// on top of the values fixed by the check.
+///
+/// A configuration can also be read from a TOML file, for example:
+/// ```toml
+/// binary = "/opt/apalache/bin/apalache-mc"
+/// jvm_options = ["-Xss16m"]
+/// jvm_heap = "4g"
+/// features = ["rows"]
+/// smt_encoding = "arrays"
+/// timeout = 600
+///
+/// [tuning_options]
+/// "search.smt.timeout" = "60"
+///
+/// [env]
+/// TMPDIR = "/scratch"
+/// ```
+#[derive(Clone, Debug)]
+pub struct CheckerConfig {
+    pub binary: PathBuf,
+    /// Passed to the JVM through the `JVM_ARGS` environment variable
+    pub jvm_options: Vec<String>,
+    /// Passed with `--features`
+    pub features: Vec<String>,
+    /// Passed with `--tuning-options`
+    pub tuning_options: BTreeMap<String, String>,
+    /// Passed with `--smt-encoding`, e.g., `oopsla19` or `arrays`
+    pub smt_encoding: Option<String>,
+    /// The name of an operator of the spec, passed with `--cinit`
+    pub constant_init: Option<String>,
+    /// Any further arguments for `apalache-mc check`
+    pub extra_args: Vec<String>,
+    /// Passed with `--out-dir`. By default, Apalache writes to the check's working directory.
+    pub out_dir: Option<PathBuf>,
+    /// Set for the Apalache process, on top of the inherited environment
+    pub env: BTreeMap<String, String>,
+    pub verbosity: Verbosity,
+    pub limits: ProcessLimits,
+}
+
+impl Default for CheckerConfig {
+    fn default() -> Self {
+        Self::new(Path::new(DEFAULT_APALACHE_BINARY))
+    }
+}
+
+/// The configuration file format; all entries are optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    binary: Option<PathBuf>,
+    jvm_options: Vec<String>,
+    jvm_heap: Option<String>,
+    features: Vec<String>,
+    tuning_options: BTreeMap<String, String>,
+    smt_encoding: Option<String>,
+    constant_init: Option<String>,
+    extra_args: Vec<String>,
+    out_dir: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    verbosity: Option<Verbosity>,
+    /// In seconds
+    timeout: Option<u64>,
+    max_concurrent: Option<usize>,
+}
+
+fn parse_verbosity(verbosity: &str) -> Result<Verbosity, String> {
+    match verbosity {
+        "normal" => Ok(Verbosity::Normal),
+        "debug" => Ok(Verbosity::Debug),
+        other => Err(format!(
+            "Unknown verbosity {}; expected normal or debug",
+            other
+        )),
+    }
+}
+
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    timeout
+        .trim()
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|e| format!("Invalid timeout {}: {}", timeout, e))
+}
+
+impl CheckerConfig {
+    pub fn new(binary: &Path) -> Self {
+        Self {
+            binary: binary.to_path_buf(),
+            jvm_options: Vec::new(),
+            features: Vec::new(),
+            tuning_options: BTreeMap::new(),
+            smt_encoding: None,
+            constant_init: None,
+            extra_args: Vec::new(),
+            out_dir: None,
+            env: BTreeMap::new(),
+            verbosity: Verbosity::Normal,
+            limits: ProcessLimits::default(),
+        }
+    }
+
+    pub fn with_jvm_option(mut self, option: &str) -> Self {
+        self.jvm_options.push(option.to_string());
+        self
+    }
+
+    pub fn with_feature(mut self, feature: &str) -> Self {
+        self.features.push(feature.to_string());
+        self
+    }
+
+    pub fn with_tuning_option(mut self, key: &str, value: &str) -> Self {
+        self.tuning_options
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_smt_encoding(mut self, encoding: &str) -> Self {
+        self.smt_encoding = Some(encoding.to_string());
+        self
+    }
+
+    pub fn with_constant_init(mut self, predicate: &str) -> Self {
+        self.constant_init = Some(predicate.to_string());
+        self
+    }
+
+    pub fn with_extra_arg(mut self, arg: &str) -> Self {
+        self.extra_args.push(arg.to_string());
+        self
+    }
+
+    pub fn with_out_dir(mut self, out_dir: &Path) -> Self {
+        self.out_dir = Some(out_dir.to_path_buf());
+        self
+    }
+
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ProcessLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reads a configuration file in the TOML format. Missing entries keep their defaults.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Can't read the configuration {}: {}", path.display(), e))?;
+        Self::from_toml(&text)
+            .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))
+    }
+
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut config = Self::default();
+        if let Some(binary) = file.binary {
+            config.binary = binary;
+        }
+        config.jvm_options = file.jvm_options;
+        config.features = file.features;
+        config.tuning_options = file.tuning_options;
+        config.smt_encoding = file.smt_encoding;
+        config.constant_init = file.constant_init;
+        config.extra_args = file.extra_args;
+        config.out_dir = file.out_dir;
+        config.env = file.env;
+        config.verbosity = file.verbosity.unwrap_or_default();
+        if let Some(heap) = file.jvm_heap {
+            config.limits = config.limits.with_jvm_heap(&heap);
+        }
+        if let Some(timeout) = file.timeout {
+            config.limits = config.limits.with_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(max_concurrent) = file.max_concurrent {
+            config.limits = config.limits.with_max_concurrent(max_concurrent);
+        }
+        Ok(config)
+    }
+
+    /// Reads the configuration from the file named by `TLA_CHECK_CONFIG`, if set, and then
+    /// applies the settings from the other `TLA_CHECK_*` environment variables on top.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = match lookup(CONFIG_FILE_ENV_VAR) {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None => Self::default(),
+        };
+        if let Some(binary) = lookup(APALACHE_BINARY_ENV_VAR) {
+            config.binary = PathBuf::from(binary);
+        }
+        if let Some(options) = lookup(JVM_OPTIONS_ENV_VAR) {
+            config
+                .jvm_options
+                .extend(options.split_whitespace().map(str::to_string));
+        }
+        if let Some(features) = lookup(FEATURES_ENV_VAR) {
+            config.features.extend(
+                features
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        if let Some(encoding) = lookup(SMT_ENCODING_ENV_VAR) {
+            config.smt_encoding = Some(encoding);
+        }
+        if let Some(out_dir) = lookup(OUT_DIR_ENV_VAR) {
+            config.out_dir = Some(PathBuf::from(out_dir));
+        }
+        if let Some(verbosity) = lookup(VERBOSITY_ENV_VAR) {
+            config.verbosity = parse_verbosity(&verbosity)?;
+        }
+        if let Some(timeout) = lookup(TIMEOUT_ENV_VAR) {
+            config.limits = config.limits.with_timeout(parse_timeout(&timeout)?);
+        }
+        Ok(config)
+    }
+
+    /// The arguments for `apalache-mc check` that don't depend on the individual check
+    pub(crate) fn check_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push(format!("--features={}", self.features.join(",")));
+        }
+        if !self.tuning_options.is_empty() {
+            let options = self
+                .tuning_options
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(":");
+            args.push(format!("--tuning-options={}", options));
+        }
+        if let Some(encoding) = &self.smt_encoding {
+            args.push(format!("--smt-encoding={}", encoding));
+        }
+        if let Some(out_dir) = &self.out_dir {
+            args.push(format!("--out-dir={}", out_dir.display()));
+        }
+        if self.verbosity == Verbosity::Debug {
+            args.push("--debug".to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    /// The options passed to the JVM, including the heap size from the limits
+    pub(crate) fn jvm_args(&self) -> Vec<String> {
+        self.jvm_options
+            .iter()
+            .cloned()
+            .chain(self.limits.jvm_heap_option())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+    use std::collections::HashMap;
+
+    #[test]
+    fn reads_file_and_environment() {
+        let dir = test_dir("config");
+        let file = dir.join("checker.toml");
+        fs::write(
+            &file,
+            r#"
+binary = "/opt/apalache/bin/apalache-mc"
+jvm_options = ["-Xss16m"]
+jvm_heap = "4g"
+features = ["rows"]
+smt_encoding = "oopsla19"
+timeout = 600
+
+[tuning_options]
+"search.smt.timeout" = "60"
+"#,
+        )
+        .unwrap();
+        let env = HashMap::from([
+            (CONFIG_FILE_ENV_VAR, file.display().to_string()),
+            (FEATURES_ENV_VAR, "no-rows, other".to_string()),
+            (SMT_ENCODING_ENV_VAR, "arrays".to_string()),
+            (VERBOSITY_ENV_VAR, "debug".to_string()),
+        ]);
+        let config = CheckerConfig::from_lookup(|name| env.get(name).cloned()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.binary, Path::new("/opt/apalache/bin/apalache-mc"));
+        assert_eq!(config.jvm_args(), vec!["-Xss16m", "-Xmx4g"]);
+        assert_eq!(config.limits.timeout, Some(Duration::from_secs(600)));
+        assert_eq!(
+            config.check_args(),
+            vec![
+                "--features=rows,no-rows,other",
+                "--tuning-options=search.smt.timeout=60",
+                "--smt-encoding=arrays",
+                "--debug",
+            ]
+        );
+
+        assert!(CheckerConfig::from_toml("bniary = \"typo\"").is_err());
+        assert!(CheckerConfig::from_lookup(
+            |name| (name == VERBOSITY_ENV_VAR).then(|| "loud".to_string())
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use super::tla_module::spec_declarations;
+use crate::{GlobalState, TlaConstantAssignment};
+
+/// A recorded name that the spec doesn't declare, with the declared name it most likely
+/// stands for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UndeclaredName {
+    pub name: String,
+    pub suggestion: Option<String>,
+}
+
+/// The differences between the names that the spec declares and the names of the
+/// recorded state and constants
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeclarationMismatch {
+    /// Declared by the spec, but not recorded
+    pub missing_variables: Vec<String>,
+    /// Recorded, but not declared by the spec
+    pub undeclared_variables: Vec<UndeclaredName>,
+    pub missing_constants: Vec<String>,
+    pub undeclared_constants: Vec<UndeclaredName>,
+}
+
+impl DeclarationMismatch {
+    pub fn is_empty(&self) -> bool {
+        self.missing_variables.is_empty()
+            && self.undeclared_variables.is_empty()
+            && self.missing_constants.is_empty()
+            && self.undeclared_constants.is_empty()
+    }
+
+    /// Whether the model checker can't be run. Missing constants aren't fatal, as they may
+    /// be fixed by the configuration rather than recorded.
+    pub fn is_fatal(&self) -> bool {
+        !(self.missing_variables.is_empty()
+            && self.undeclared_variables.is_empty()
+            && self.undeclared_constants.is_empty())
+    }
+}
+
+impl Display for DeclarationMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "The recorded names don't match the declarations of the spec:"
+        )?;
+        for (kind, missing, undeclared) in [
+            (
+                "variable",
+                &self.missing_variables,
+                &self.undeclared_variables,
+            ),
+            (
+                "constant",
+                &self.missing_constants,
+                &self.undeclared_constants,
+            ),
+        ] {
+            for name in undeclared {
+                write!(f, "  {} {} is not declared by the spec", kind, name.name)?;
+                match &name.suggestion {
+                    Some(suggestion) => writeln!(f, "; did you mean {}?", suggestion)?,
+                    None => writeln!(f)?,
+                }
+            }
+            for name in missing {
+                writeln!(f, "  {} {} is declared, but wasn't recorded", kind, name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of single-character insertions, deletions and substitutions that turn `a`
+/// into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The closest candidate, if it's close enough to be a plausible misspelling
+fn suggestion<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<String> {
+    let threshold = (name.chars().count() / 3).max(2);
+    candidates
+        .map(|candidate| {
+            let distance = edit_distance(&name.to_lowercase(), &candidate.to_lowercase());
+            (distance, candidate)
+        })
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// Returns the missing and the undeclared names. Undeclared names are matched against the
+/// missing ones first, and then against all declared ones.
+fn compare<'a>(
+    declared: &BTreeSet<String>,
+    recorded: impl Iterator<Item = &'a String>,
+) -> (Vec<String>, Vec<UndeclaredName>) {
+    let recorded: BTreeSet<&String> = recorded.collect();
+    let missing: Vec<String> = declared
+        .iter()
+        .filter(|name| !recorded.contains(name))
+        .cloned()
+        .collect();
+    let undeclared = recorded
+        .into_iter()
+        .filter(|name| !declared.contains(*name))
+        .map(|name| UndeclaredName {
+            name: name.clone(),
+            suggestion: suggestion(name, missing.iter())
+                .or_else(|| suggestion(name, declared.iter())),
+        })
+        .collect();
+    (missing, undeclared)
+}
+
+/// Compares the variables and constants that the module declares (itself or through the
+/// modules it extends) with the recorded states and constants
+pub fn check_declarations<'a>(
+    tla_module: &Path,
+    states: impl IntoIterator<Item = &'a GlobalState>,
+    constants: &TlaConstantAssignment,
+) -> Result<DeclarationMismatch, String> {
+    let declarations = spec_declarations(tla_module)?;
+    let mut mismatch = DeclarationMismatch::default();
+    for state in states {
+        let (missing, undeclared) = compare(&declarations.variables, state.0 .0.keys());
+        for name in missing {
+            if !mismatch.missing_variables.contains(&name) {
+                mismatch.missing_variables.push(name);
+            }
+        }
+        for name in undeclared {
+            if !mismatch.undeclared_variables.contains(&name) {
+                mismatch.undeclared_variables.push(name);
+            }
+        }
+    }
+    (mismatch.missing_constants, mismatch.undeclared_constants) =
+        compare(&declarations.constants, constants.constants.keys());
+    Ok(mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{
+        check_tla_code_link_with, FailureKind, FakeChecker, PredicateDescription, Verdict,
+    };
+    use crate::test_helpers::{no_constants, test_dir};
+    use crate::{ResolvedStatePair, ToTla};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn reports_misspelt_names() {
+        let dir = test_dir("declarations");
+        let module = dir.join("Ledger.tla");
+        std::fs::write(
+            &module,
+            "---- MODULE Ledger ----\nEXTENDS Naturals, Base\nCONSTANTS FEE, MAX_ACCOUNTS\nVARIABLES\n  \\* @type: Int;\n  balance,\n  pending\n====\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Base.tla"),
+            "---- MODULE Base ----\nVARIABLE pc\n====\n",
+        )
+        .unwrap();
+        let mut state = GlobalState::new();
+        state.add("ballance", 1_u64.to_tla_value());
+        state.add("pc", "Start".to_tla_value());
+        state.add("counter", 1_u64.to_tla_value());
+        let constants = TlaConstantAssignment {
+            constants: BTreeMap::from([("Fee".to_string(), 1_u64.to_tla_value())]),
+        };
+
+        let mismatch = check_declarations(&module, [&state, &state], &constants).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mismatch.missing_variables, vec!["balance", "pending"]);
+        assert_eq!(
+            mismatch.undeclared_variables,
+            vec![
+                UndeclaredName {
+                    name: "ballance".to_string(),
+                    suggestion: Some("balance".to_string()),
+                },
+                UndeclaredName {
+                    name: "counter".to_string(),
+                    suggestion: None,
+                },
+            ]
+        );
+        assert_eq!(mismatch.missing_constants, vec!["FEE", "MAX_ACCOUNTS"]);
+        assert_eq!(
+            mismatch.undeclared_constants[0].suggestion.as_deref(),
+            Some("FEE")
+        );
+        assert!(mismatch
+            .to_string()
+            .contains("variable ballance is not declared by the spec; did you mean balance?"));
+        assert!(mismatch.is_fatal());
+    }
+
+    #[test]
+    fn allows_constants_fixed_by_the_configuration() {
+        let dir = test_dir("declarations_config");
+        let module = dir.join("Counter.tla");
+        std::fs::write(
+            &module,
+            "---- MODULE Counter ----\nEXTENDS Naturals\nCONSTANT MAX\nVARIABLE cnt\nNext == cnt < MAX /\\ cnt' = cnt + 1\n====\n",
+        )
+        .unwrap();
+        let state = |cnt: u64| {
+            let mut state = GlobalState::new();
+            state.add("cnt", cnt.to_tla_value());
+            state
+        };
+        let constants = no_constants();
+
+        let mismatch = check_declarations(&module, [&state(1)], &constants).unwrap();
+        // MAX is left to a `constant_init` of the checker configuration
+        let check = |end: u64| {
+            check_tla_code_link_with(
+                &FakeChecker::new().when_contains("cnt' = 3", Verdict::Fail(FailureKind::Deadlock)),
+                PredicateDescription {
+                    tla_module: module.clone(),
+                    transition_predicate: "Next".to_string(),
+                    predicate_parameters: vec![],
+                },
+                ResolvedStatePair {
+                    start: state(1),
+                    end: state(end),
+                },
+                constants.clone(),
+            )
+        };
+        let passed = check(2);
+        let failed = check(3).expect_err("The check should fail");
+        if let Some(bundle) = failed.bundle() {
+            std::fs::remove_dir_all(bundle).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mismatch.missing_constants, vec!["MAX"]);
+        assert!(!mismatch.is_fatal());
+        assert!(passed.is_ok(), "{:?}", passed);
+        // Failures name the constants, as the configuration may have got them wrong
+        assert_eq!(
+            failed.declaration_mismatch.unwrap().missing_constants,
+            vec!["MAX"]
+        );
+    }
+}
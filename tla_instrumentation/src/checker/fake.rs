@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::bundle::sh_quote;
+use super::{ApalacheError, CheckFailure, FailureKind, ModelChecker, TransitionCheck};
+
+/// The outcome that a `FakeChecker` reports for a check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail(FailureKind),
+    Timeout,
+    SetupError(String),
+}
+
+/// A check that a `FakeChecker` was asked to run
+#[derive(Clone, Debug)]
+pub struct RecordedCheck {
+    pub module: PathBuf,
+    /// The text of the generated module, read when the check ran
+    pub module_text: String,
+    pub check: TransitionCheck,
+}
+
+type Rule = (Box<dyn Fn(&str) -> bool + Send + Sync>, Verdict);
+
+/// A model checker for tests that doesn't run anything. It records the generated modules
+/// and decides the verdicts from a script: queued verdicts are used first, in order;
+/// then the first rule that matches the text of the generated module; and finally the
+/// default verdict, which is `Verdict::Pass` unless set otherwise.
+pub struct FakeChecker {
+    queue: Mutex<VecDeque<Verdict>>,
+    rules: Vec<Rule>,
+    default: Verdict,
+    extra_definitions: Vec<String>,
+    version: String,
+    recorded: Mutex<Vec<RecordedCheck>>,
+}
+
+impl Default for FakeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeChecker {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            rules: Vec::new(),
+            default: Verdict::Pass,
+            extra_definitions: Vec::new(),
+            version: "fake".to_string(),
+            recorded: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reports `verdict` for the next check that doesn't use up an earlier queued verdict
+    pub fn then(self, verdict: Verdict) -> Self {
+        self.queue.lock().unwrap().push_back(verdict);
+        self
+    }
+
+    /// Reports `verdict` for generated modules for which `matches` holds
+    pub fn when(
+        mut self,
+        matches: impl Fn(&str) -> bool + Send + Sync + 'static,
+        verdict: Verdict,
+    ) -> Self {
+        self.rules.push((Box::new(matches), verdict));
+        self
+    }
+
+    /// Reports `verdict` for generated modules that contain `pattern`
+    pub fn when_contains(self, pattern: &str, verdict: Verdict) -> Self {
+        let pattern = pattern.to_string();
+        self.when(move |text| text.contains(&pattern), verdict)
+    }
+
+    pub fn with_default(mut self, verdict: Verdict) -> Self {
+        self.default = verdict;
+        self
+    }
+
+    /// Definitions to append to the generated modules, as a real backend could
+    pub fn with_extra_definitions(mut self, definitions: Vec<String>) -> Self {
+        self.extra_definitions = definitions;
+        self
+    }
+
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    /// The checks run so far, in order
+    pub fn recorded(&self) -> Vec<RecordedCheck> {
+        self.recorded.lock().unwrap().clone()
+    }
+
+    fn verdict(&self, module_text: &str) -> Verdict {
+        if let Some(verdict) = self.queue.lock().unwrap().pop_front() {
+            return verdict;
+        }
+        self.rules
+            .iter()
+            .find(|(matches, _)| matches(module_text))
+            .map(|(_, verdict)| verdict.clone())
+            .unwrap_or(self.default.clone())
+    }
+}
+
+impl ModelChecker for FakeChecker {
+    fn extra_definitions(
+        &self,
+        _tla_module: &Path,
+        _check: &TransitionCheck,
+    ) -> Result<Vec<String>, String> {
+        Ok(self.extra_definitions.clone())
+    }
+
+    fn check(&self, generated_module: &Path, check: &TransitionCheck) -> Result<(), ApalacheError> {
+        let module_text = fs::read_to_string(generated_module).map_err(|e| {
+            ApalacheError::SetupError(format!(
+                "Can't read the generated module {}: {}",
+                generated_module.display(),
+                e
+            ))
+        })?;
+        let verdict = self.verdict(&module_text);
+        self.recorded.lock().unwrap().push(RecordedCheck {
+            module: generated_module.to_path_buf(),
+            module_text,
+            check: check.clone(),
+        });
+        match verdict {
+            Verdict::Pass => Ok(()),
+            Verdict::Fail(kind) => Err(ApalacheError::CheckFailed(Box::new(CheckFailure {
+                exit_code: Some(if kind == FailureKind::Deadlock {
+                    DEADLOCK_EXIT_CODE
+                } else {
+                    255
+                }),
+                module: generated_module.to_path_buf(),
+                excerpt: kind.to_string(),
+                output: kind.to_string(),
+                kind,
+            }))),
+            Verdict::Timeout => Err(ApalacheError::Timeout {
+                elapsed: Duration::ZERO,
+                module: generated_module.into(),
+            }),
+            Verdict::SetupError(message) => Err(ApalacheError::SetupError(message)),
+        }
+    }
+
+    fn version(&self) -> String {
+        self.version.clone()
+    }
+}
+
+/// What Apalache prints when the transition isn't possible
+const DEADLOCK_OUTPUT: &str = "Step 1: picking a transition out of 1 transition(s)\nState 1: Checking 1 state invariants\nFound a deadlock.\nThe outcome is: Deadlock";
+/// Apalache's exit code when it finds a counterexample
+const DEADLOCK_EXIT_CODE: i32 = 12;
+
+/// A fake `apalache-mc` executable (a shell script) for end-to-end tests of running the
+/// model checker as a process. It answers `version`, and for `check` appends the
+/// arguments to a log file and answers according to the rules: the first rule whose
+/// pattern occurs in the checked module determines the output and the exit code. If no
+/// rule matches, the check passes.
+#[derive(Clone, Debug)]
+pub struct FakeApalache {
+    dir: PathBuf,
+    rules: Vec<(String, i32, String)>,
+}
+
+impl FakeApalache {
+    /// The script and its log are written to `dir`
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn when(mut self, pattern: &str, exit_code: i32, output: &str) -> Self {
+        self.rules
+            .push((pattern.to_string(), exit_code, output.to_string()));
+        self
+    }
+
+    /// Reports a deadlock, like Apalache does, for modules that contain `pattern`
+    pub fn deadlock_when(self, pattern: &str) -> Self {
+        self.when(pattern, DEADLOCK_EXIT_CODE, DEADLOCK_OUTPUT)
+    }
+
+    pub fn log(&self) -> PathBuf {
+        self.dir.join("fake_apalache.log")
+    }
+
+    /// The arguments of the `check` calls so far, one line per call
+    pub fn calls(&self) -> Vec<String> {
+        fs::read_to_string(self.log())
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Writes the script, and returns its path
+    #[cfg(unix)]
+    pub fn write(&self) -> Result<PathBuf, String> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let rules: String = self
+            .rules
+            .iter()
+            .map(|(pattern, exit_code, output)| {
+                format!(
+                    "if grep -qF -- {} \"$module\"; then\n  printf '%s\\n' {}\n  exit {}\nfi\n",
+                    sh_quote(pattern),
+                    sh_quote(output),
+                    exit_code
+                )
+            })
+            .collect();
+        let script = format!(
+            "#!/bin/sh\n\
+             if [ \"$1\" = version ]; then\n  echo fake\n  exit 0\nfi\n\
+             echo \"$@\" >> {}\n\
+             for arg; do module=\"$arg\"; done\n\
+             {}\
+             echo 'The outcome is: NoError'\n",
+            sh_quote(&self.log().to_string_lossy()),
+            rules
+        );
+        let path = self.dir.join("apalache-mc");
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, script))
+            .and_then(|_| fs::set_permissions(&path, fs::Permissions::from_mode(0o755)))
+            .map_err(|e| format!("Can't write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+    use std::collections::HashMap;
+
+    #[test]
+    fn scripted_verdicts() {
+        let dir = test_dir("fake");
+        let module = dir.join("Generated.tla");
+        fs::write(&module, "cnt' = 7").unwrap();
+        let check = TransitionCheck {
+            init_predicate: "Init".to_string(),
+            next_predicate: "Next".to_string(),
+            post_state: HashMap::new(),
+            constants: HashMap::new(),
+            length: 1,
+            constant_init: None,
+        };
+        let checker = FakeChecker::new()
+            .then(Verdict::Timeout)
+            .when_contains("cnt' = 7", Verdict::Fail(FailureKind::Deadlock));
+        assert!(matches!(
+            checker.check(&module, &check),
+            Err(ApalacheError::Timeout { .. })
+        ));
+        assert!(matches!(
+            checker.check(&module, &check),
+            Err(ApalacheError::CheckFailed(failure)) if failure.kind == FailureKind::Deadlock
+        ));
+        fs::write(&module, "cnt' = 6").unwrap();
+        assert!(checker.check(&module, &check).is_ok());
+        let recorded = checker.recorded();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(recorded[2].module_text, "cnt' = 6");
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use super::work_dir::discard_artifacts;
+use super::{
+    check_tla_code_link_raw_with, check_tla_code_link_with, ApalacheError, ModelChecker,
+    PredicateDescription, TlaCheckError,
+};
+use crate::{ResolvedStatePair, TlaConstantAssignment, TlaValue, ValueChange};
+
+/// How a variable in a minimal failing set changed from the pre- to the post-state
+#[derive(Clone, Debug)]
+pub struct VariableDiff {
+    pub pre: Option<TlaValue>,
+    pub post: TlaValue,
+    pub changes: Vec<ValueChange>,
+}
+
+/// The result of narrowing down a failed check
+#[derive(Clone, Debug)]
+pub struct Localization {
+    /// A minimal set of variables whose recorded post-state values together make the
+    /// transition impossible: dropping the constraint on any one of them lets the check
+    /// pass. Empty if the transition isn't enabled in the pre-state at all.
+    pub variables: BTreeSet<String>,
+    pub diffs: BTreeMap<String, VariableDiff>,
+    /// The number of model checker runs it took
+    pub checks: usize,
+}
+
+impl Display for Localization {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.variables.is_empty() {
+            return write!(
+                f,
+                "The transition is not enabled in the pre-state, whatever the post-state"
+            );
+        }
+        writeln!(
+            f,
+            "The recorded post-state values of these variables are incompatible with the transition:"
+        )?;
+        for (name, diff) in &self.diffs {
+            if diff.changes.is_empty() {
+                writeln!(f, "  {} (unchanged: {})", name, diff.post)?;
+            }
+            for change in &diff.changes {
+                writeln!(f, "  {}{}", name, change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Oracle<'a> {
+    checker: &'a dyn ModelChecker,
+    predicate: &'a PredicateDescription,
+    /// The predicate's parameters, as bound for the pair
+    parameters: Vec<String>,
+    pre_state: HashMap<String, String>,
+    post_state: BTreeMap<String, String>,
+    constants: HashMap<String, String>,
+    checks: usize,
+}
+
+impl Oracle<'_> {
+    /// Whether the check fails when only the given variables are constrained in the
+    /// post-state. Failures other than deadlocks abort the localisation.
+    fn fails(&mut self, variables: &BTreeSet<String>) -> Result<bool, ApalacheError> {
+        self.checks += 1;
+        let post_state = variables
+            .iter()
+            .map(|v| (v.clone(), self.post_state[v].clone()))
+            .collect();
+        match check_tla_code_link_raw_with(
+            self.checker,
+            &self.predicate.tla_module,
+            self.predicate.transition_predicate.clone(),
+            self.parameters.clone(),
+            self.pre_state.clone(),
+            post_state,
+            self.constants.clone(),
+        ) {
+            Ok(()) => Ok(false),
+            Err(error) if error.is_deadlock() => {
+                // Failing runs are expected here, so don't keep them
+                discard_artifacts(&error);
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// QuickXplain: finds a minimal subset of `candidates` that, together with
+    /// `background`, fails, assuming that the whole set does. It bisects the candidates,
+    /// so it needs far fewer runs than removing one variable at a time when the failing
+    /// set is small.
+    fn quick_xplain(
+        &mut self,
+        background: &BTreeSet<String>,
+        background_changed: bool,
+        candidates: &[String],
+    ) -> Result<BTreeSet<String>, ApalacheError> {
+        if background_changed && self.fails(background)? {
+            return Ok(BTreeSet::new());
+        }
+        if candidates.len() == 1 {
+            return Ok(candidates.iter().cloned().collect());
+        }
+        let (first, second) = candidates.split_at(candidates.len() / 2);
+        let with_first = background.iter().chain(first).cloned().collect();
+        let from_second = self.quick_xplain(&with_first, !first.is_empty(), second)?;
+        let with_second = background.union(&from_second).cloned().collect();
+        let from_first = self.quick_xplain(&with_second, !from_second.is_empty(), first)?;
+        Ok(from_first.union(&from_second).cloned().collect())
+    }
+}
+
+/// Re-runs a failed check with subsets of the post-state constraints, to find a minimal
+/// set of variables whose recorded values are incompatible with the transition. The
+/// failure with all constraints is taken from the given error, rather than checked again.
+/// Returns `None` if the check didn't fail with a deadlock.
+pub fn localize_failure(
+    checker: &dyn ModelChecker,
+    predicate: &PredicateDescription,
+    failure: &TlaCheckError,
+) -> Result<Option<Localization>, ApalacheError> {
+    if !failure.apalache_error.is_deadlock() {
+        return Ok(None);
+    }
+    let state_pair = &failure.pair;
+    let mut oracle = Oracle {
+        checker,
+        predicate,
+        parameters: predicate
+            .bind_parameters(state_pair)
+            .map_err(ApalacheError::SetupError)?,
+        pre_state: state_pair.start.to_map(),
+        post_state: state_pair.end.to_map().into_iter().collect(),
+        constants: failure.constants.to_map(),
+        checks: 0,
+    };
+    let all: Vec<String> = oracle.post_state.keys().cloned().collect();
+    let variables = if all.is_empty() {
+        BTreeSet::new()
+    } else {
+        oracle.quick_xplain(&BTreeSet::new(), true, &all)?
+    };
+    let diffs = variables
+        .iter()
+        .map(|name| {
+            let pre = state_pair.start.0 .0.get(name).cloned();
+            let post = state_pair.end.0 .0[name].clone();
+            let changes = pre.as_ref().map(|pre| pre.diff(&post)).unwrap_or_default();
+            (name.clone(), VariableDiff { pre, post, changes })
+        })
+        .collect();
+    Ok(Some(Localization {
+        variables,
+        diffs,
+        checks: oracle.checks,
+    }))
+}
+
+/// Like `check_tla_code_link_with`, but if the transition turns out to be impossible,
+/// also localises the failure. Errors during the localisation are ignored, leaving the
+/// localisation empty.
+pub fn check_tla_code_link_localized(
+    checker: &dyn ModelChecker,
+    predicate: PredicateDescription,
+    state_pair: ResolvedStatePair,
+    constants: TlaConstantAssignment,
+) -> Result<(), TlaCheckError> {
+    check_tla_code_link_with(checker, predicate.clone(), state_pair, constants).map_err(
+        |mut error| {
+            error.localization = localize_failure(checker, &predicate, &error)
+                .ok()
+                .flatten()
+                .map(Box::new);
+            error
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{FailureKind, FakeChecker, Verdict};
+    use crate::test_helpers::{no_constants, test_dir};
+    use crate::{GlobalState, ToTla};
+
+    #[test]
+    fn finds_minimal_failing_variables() {
+        let dir = test_dir("localize");
+        let module = dir.join("Spec.tla");
+        std::fs::write(
+            &module,
+            "---- MODULE Spec ----\nVARIABLES v, w, x, y, z\n====\n",
+        )
+        .unwrap();
+        let predicate = PredicateDescription {
+            tla_module: module,
+            transition_predicate: "Next".to_string(),
+            predicate_parameters: vec![],
+        };
+        let state = |values: [u64; 5]| {
+            let mut state = GlobalState::new();
+            for (name, value) in ["v", "w", "x", "y", "z"].iter().zip(values) {
+                state.add(name, value.to_tla_value());
+            }
+            state
+        };
+        let pair = ResolvedStatePair {
+            start: state([1, 2, 3, 4, 5]),
+            end: state([1, 3, 5, 7, 5]),
+        };
+        let constants = no_constants();
+        // Rejects a transition iff the post-state pins both `x' = 5` and `y' = 7`
+        let checker = FakeChecker::new().when(
+            |text| text.contains("x' = 5") && text.contains("y' = 7"),
+            Verdict::Fail(FailureKind::Deadlock),
+        );
+
+        let error = check_tla_code_link_localized(&checker, predicate.clone(), pair, constants)
+            .expect_err("The check should fail");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let localization = error.localization.expect("No localization");
+        assert_eq!(
+            localization.variables,
+            BTreeSet::from(["x".to_string(), "y".to_string()])
+        );
+        assert_eq!(
+            localization.diffs["x"].changes,
+            vec![ValueChange {
+                path: String::new(),
+                old: Some(3_u64.to_tla_value()),
+                new: Some(5_u64.to_tla_value()),
+            }]
+        );
+        // Only the original check constrained the whole post-state
+        assert_eq!(checker.recorded().len(), localization.checks + 1);
+        let full_checks = checker
+            .recorded()
+            .iter()
+            .filter(|run| {
+                ["v", "w", "x", "y", "z"]
+                    .iter()
+                    .all(|v| run.module_text.contains(&format!("{}' =", v)))
+            })
+            .count();
+        assert_eq!(full_checks, 1);
+    }
+
+    #[test]
+    fn diffs_nested_values() {
+        let old = BTreeMap::from([("alice", vec![1_u64, 2]), ("bob", vec![3])]).to_tla_value();
+        let new = BTreeMap::from([("alice", vec![1_u64, 4, 5])]).to_tla_value();
+        let changes: Vec<String> = old.diff(&new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "[\"alice\"][2]: 2 -> 4",
+                "[\"alice\"][3]: (none) -> 5",
+                "[\"bob\"]: <<3>> -> (none)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::path::PathBuf;
+
+use super::bundle::{bundle_dir, complete_bundle, run_check};
+use super::work_dir::WorkDir;
+use super::{
+    artifacts_dir, check_recorded_names, mk_constant_definitions, mk_init_predicate,
+    new_wrapper_module, ApalacheError, DeclarationMismatch, ModelChecker, PredicateDescription,
+    TransitionCheck, INIT_PREDICATE_NAME, NEXT_PREDICATE_NAME,
+};
+use crate::{GlobalState, ResolvedStatePair, TlaConstantAssignment, UpdateTrace};
+
+/// Counts the steps taken so far, such that each step can be constrained separately
+const STEP_VARIABLE_NAME: &str = "Check_Code_Link_Step";
+
+#[derive(Debug)]
+pub struct TraceCheckError {
+    pub apalache_error: ApalacheError,
+    /// The recorded states that the behaviour had to go through, starting with the
+    /// initial one
+    pub states: Vec<GlobalState>,
+    pub constants: TlaConstantAssignment,
+    /// As for `TlaCheckError::declaration_mismatch`
+    pub declaration_mismatch: Option<Box<DeclarationMismatch>>,
+}
+
+impl TraceCheckError {
+    /// The directory for reproducing the failure, as for `TlaCheckError::bundle`
+    pub fn bundle(&self) -> Option<PathBuf> {
+        bundle_dir(&self.apalache_error)
+    }
+}
+
+/// A step of the checked behaviour: the predicate (applied to its parameters) that has
+/// to take the previous state to the given one
+struct Step {
+    predicate: String,
+    state: GlobalState,
+}
+
+/// Turns the state pairs into steps. When a pair doesn't start in the state in which the
+/// previous one ended (e.g., because other processes ran in between), the environment
+/// predicate has to explain the difference in an extra step.
+fn trace_steps(
+    trace: &UpdateTrace,
+    transition: impl Fn(&ResolvedStatePair) -> Result<String, String>,
+    environment: Option<&str>,
+) -> Result<Vec<Step>, String> {
+    let mut steps: Vec<Step> = Vec::new();
+    for (i, pair) in trace.state_pairs.iter().enumerate() {
+        if let Some(previous) = steps.last() {
+            if previous.state != pair.start {
+                let predicate = environment.ok_or(format!(
+                    "State pair {} doesn't start in the state in which pair {} ended, and no environment predicate was given",
+                    i,
+                    i - 1
+                ))?;
+                steps.push(Step {
+                    predicate: predicate.to_string(),
+                    state: pair.start.clone(),
+                });
+            }
+        }
+        steps.push(Step {
+            predicate: transition(pair)?,
+            state: pair.end.clone(),
+        });
+    }
+    Ok(steps)
+}
+
+fn mk_multi_step_next(steps: &[Step]) -> String {
+    let arms = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let post_state_constraint = step
+                .state
+                .0
+                 .0
+                .iter()
+                .map(|(k, v)| format!("\n          /\\ {}' = {}", k, v))
+                .collect::<String>();
+            format!(
+                "{} = {} ->\n          /\\ {}{}",
+                STEP_VARIABLE_NAME, i, step.predicate, post_state_constraint
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n       [] ");
+    format!(
+        "{} ==\n  /\\ CASE {}\n       [] OTHER -> FALSE\n  /\\ {}' = {} + 1",
+        NEXT_PREDICATE_NAME, arms, STEP_VARIABLE_NAME, STEP_VARIABLE_NAME
+    )
+}
+
+/// Checks that the whole trace is a behaviour of the spec, in a single bounded run: the
+/// initial state is the start of the first pair, and the state after each step is
+/// constrained to the recorded one. This catches problems that single pairs don't show,
+/// e.g., a response that doesn't match the request made several steps earlier.
+///
+/// If the update doesn't resume in the state in which it stopped (e.g., because other
+/// processes changed the state in the meantime), the `environment` predicate, typically
+/// the spec's `Next`, must allow that change.
+pub fn check_trace(
+    checker: &dyn ModelChecker,
+    predicate: &PredicateDescription,
+    environment: Option<&str>,
+    trace: &UpdateTrace,
+) -> Result<(), TraceCheckError> {
+    let Some(first) = trace.state_pairs.first() else {
+        return Ok(());
+    };
+    let mut states = vec![first.start.clone()];
+    let mut declaration_mismatch = None;
+    let result = trace_steps(trace, |pair| predicate.transition_for(pair), environment)
+        .map_err(ApalacheError::SetupError)
+        .and_then(|steps| {
+            declaration_mismatch = check_recorded_names(
+                &predicate.tla_module,
+                std::iter::once(&first.start).chain(steps.iter().map(|step| &step.state)),
+                &trace.constants,
+            )?;
+            states.extend(steps.iter().map(|step| step.state.clone()));
+            let (constant_definitions, constant_init) =
+                mk_constant_definitions(&predicate.tla_module, trace.constants.to_map())
+                    .map_err(ApalacheError::SetupError)?;
+            let mut post_state = steps[steps.len() - 1].state.to_map();
+            post_state.insert(STEP_VARIABLE_NAME.to_string(), steps.len().to_string());
+            let check = TransitionCheck {
+                init_predicate: INIT_PREDICATE_NAME.to_string(),
+                next_predicate: NEXT_PREDICATE_NAME.to_string(),
+                post_state,
+                constants: trace.constants.to_map(),
+                length: steps.len(),
+                constant_init,
+            };
+            let extra_definitions = checker
+                .extra_definitions(&predicate.tla_module, &check)
+                .map_err(ApalacheError::SetupError)?;
+            let variable_declaration =
+                format!("VARIABLE\n  \\* @type: Int;\n  {}", STEP_VARIABLE_NAME);
+            let init_predicate = format!(
+                "{}\n  /\\ {} = 0",
+                mk_init_predicate(first.start.to_map()),
+                STEP_VARIABLE_NAME
+            );
+            let work_dir =
+                WorkDir::new(&predicate.tla_module).map_err(ApalacheError::SetupError)?;
+            let result = new_wrapper_module(
+                work_dir.path(),
+                &predicate.tla_module,
+                constant_definitions
+                    .into_iter()
+                    .chain([
+                        variable_declaration,
+                        init_predicate,
+                        mk_multi_step_next(&steps),
+                    ])
+                    .chain(extra_definitions)
+                    .collect(),
+            )
+            .map_err(ApalacheError::SetupError)
+            .and_then(|new_module| run_check(checker, &new_module, &check));
+            work_dir.finish(result, &artifacts_dir())
+        });
+    if let Err(apalache_error) = &result {
+        complete_bundle(
+            apalache_error,
+            &states.iter().collect::<Vec<_>>(),
+            &trace.constants,
+        );
+    }
+    result.map_err(|apalache_error| TraceCheckError {
+        apalache_error,
+        states,
+        constants: trace.constants.clone(),
+        declaration_mismatch,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{test_dir, update};
+    use crate::ToTla;
+
+    fn state(counter: u64) -> GlobalState {
+        let mut state = GlobalState::new();
+        state.add("counter", counter.to_tla_value());
+        state
+    }
+
+    fn trace(pairs: Vec<(u64, u64)>) -> UpdateTrace {
+        crate::test_helpers::trace(
+            update("can", "pid"),
+            pairs
+                .into_iter()
+                .map(|(start, end)| ResolvedStatePair {
+                    start: state(start),
+                    end: state(end),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn constrains_every_step() {
+        let inc = |_: &ResolvedStatePair| Ok("Inc(\"pid\")".to_string());
+        let steps = trace_steps(&trace(vec![(0, 1), (2, 3)]), inc, Some("Next")).unwrap();
+        assert_eq!(
+            mk_multi_step_next(&steps),
+            "\
+Check_Code_Link_Next ==
+  /\\ CASE Check_Code_Link_Step = 0 ->
+          /\\ Inc(\"pid\")
+          /\\ counter' = 1
+       [] Check_Code_Link_Step = 1 ->
+          /\\ Next
+          /\\ counter' = 2
+       [] Check_Code_Link_Step = 2 ->
+          /\\ Inc(\"pid\")
+          /\\ counter' = 3
+       [] OTHER -> FALSE
+  /\\ Check_Code_Link_Step' = Check_Code_Link_Step + 1"
+        );
+        assert!(trace_steps(&trace(vec![(0, 1), (2, 3)]), inc, None).is_err());
+        assert_eq!(
+            trace_steps(&trace(vec![(0, 1), (1, 2)]), inc, None)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_traces_with_tlc() {
+        use crate::checker::Tlc;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::Path;
+
+        let dir = test_dir("tlc_trace");
+        let captured = dir.join("captured");
+        fs::create_dir_all(&captured).unwrap();
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nEXTENDS Naturals\nVARIABLE counter\nNext == counter' = counter + 1\n====\n",
+        )
+        .unwrap();
+        // Stands in for the JVM: keeps the generated module, which TLC would check
+        let java = dir.join("java");
+        fs::write(
+            &java,
+            format!("#!/bin/sh\ncp ./Code_Link_*.tla '{}'\n", captured.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&java, fs::Permissions::from_mode(0o755)).unwrap();
+        let tlc = Tlc {
+            java,
+            ..Tlc::new(Path::new("tla2tools.jar"))
+        };
+        let predicate = PredicateDescription {
+            tla_module: module,
+            transition_predicate: "Next".to_string(),
+            predicate_parameters: vec![],
+        };
+
+        let result = check_trace(&tlc, &predicate, None, &trace(vec![(0, 1), (1, 2)]));
+        let generated = fs::read_dir(&captured)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .next()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok(), "{:?}", result);
+        // Both step counters are declared once, and only advanced by their own next
+        // predicate
+        assert_eq!(
+            generated
+                .matches("VARIABLE\n  \\* @type: Int;\n  Check_Code_Link_Step\n")
+                .count(),
+            1
+        );
+        assert_eq!(
+            generated
+                .matches("VARIABLE Check_Code_Link_TLC_Step\n")
+                .count(),
+            1
+        );
+        assert_eq!(generated.matches("Check_Code_Link_Step' =").count(), 1);
+        assert_eq!(generated.matches("Check_Code_Link_TLC_Step' =").count(), 1);
+        // TLC may only stutter in the final state, after both steps
+        assert!(generated.contains(
+            "\\/ Check_Code_Link_TLC_Step < 2 /\\ Check_Code_Link_Next /\\ Check_Code_Link_TLC_Step' = Check_Code_Link_TLC_Step + 1"
+        ));
+        assert!(generated.contains(
+            "Check_Code_Link_Post ==\n  /\\ Check_Code_Link_Step = 2\n  /\\ counter = 2"
+        ));
+        assert!(generated.contains(
+            "\\/ Check_Code_Link_Post /\\ Check_Code_Link_TLC_Step = 2 /\\ UNCHANGED <<Check_Code_Link_Step, counter, Check_Code_Link_TLC_Step>>"
+        ));
+    }
+}
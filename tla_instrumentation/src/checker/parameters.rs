@@ -0,0 +1,215 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{add_parameters, PredicateDescription};
+use crate::{ResolvedStatePair, TlaValue};
+
+type Extractor = dyn Fn(&ResolvedStatePair) -> Result<TlaValue, String> + Send + Sync;
+
+/// A predicate parameter whose value is only known from the recorded state pair, e.g.,
+/// the ID of the process that took the step
+#[derive(Clone)]
+pub struct ParameterBinding {
+    /// Describes the value in error messages, e.g., "the process ID"
+    pub description: String,
+    extract: Arc<Extractor>,
+}
+
+impl fmt::Debug for ParameterBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ParameterBinding({})", self.description)
+    }
+}
+
+/// The single value of the given kind, or an error naming what was found instead
+fn single(values: BTreeSet<TlaValue>, what: &str) -> Result<TlaValue, String> {
+    let mut values = values.into_iter();
+    match (values.next(), values.next()) {
+        (Some(value), None) => Ok(value),
+        (None, _) => Err(format!("No {} found", what)),
+        (Some(first), Some(second)) => Err(format!(
+            "Ambiguous {}: found both {} and {}",
+            what, first, second
+        )),
+    }
+}
+
+impl ParameterBinding {
+    pub fn new(
+        description: &str,
+        extract: impl Fn(&ResolvedStatePair) -> Result<TlaValue, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            description: description.to_string(),
+            extract: Arc::new(extract),
+        }
+    }
+
+    /// The ID of the process that took the step. The recorded `pc` only has an entry for
+    /// the process of the update.
+    pub fn process_id() -> Self {
+        Self::new("the process ID", |pair| match pair.start.get("pc") {
+            Some(TlaValue::Function(pc)) => single(pc.keys().cloned().collect(), "process in pc"),
+            other => Err(format!("Expected pc to be a function, got {:?}", other)),
+        })
+    }
+
+    /// The value of a variable in the pre-state
+    pub fn pre_state(variable: &str) -> Self {
+        let variable = variable.to_string();
+        Self::new(
+            &format!("the pre-state value of {}", variable),
+            move |pair| {
+                pair.start
+                    .get(&variable)
+                    .cloned()
+                    .ok_or(format!("The pre-state has no variable {}", variable))
+            },
+        )
+    }
+
+    /// A field of the messages in a buffer in the pre-state, e.g., the `caller` of the
+    /// response that the step consumes. The buffer can be a sequence or a set of records,
+    /// and the field must have the same value in all of them.
+    pub fn buffer_field(buffer: &str, field: &str) -> Self {
+        let (buffer, field) = (buffer.to_string(), field.to_string());
+        Self::new(
+            &format!("the {} in buffer {}", field, buffer),
+            move |pair| {
+                let messages: Vec<&TlaValue> = match pair.start.get(&buffer) {
+                    Some(TlaValue::Seq(messages)) => messages.iter().collect(),
+                    Some(TlaValue::Set(messages)) => messages.iter().collect(),
+                    other => {
+                        return Err(format!(
+                            "Expected buffer {} to be a sequence or a set, got {:?}",
+                            buffer, other
+                        ))
+                    }
+                };
+                let values = messages
+                    .into_iter()
+                    .map(|message| match message {
+                        TlaValue::Record(fields) => fields.get(&field).cloned().ok_or(format!(
+                            "A message in buffer {} has no field {}",
+                            buffer, field
+                        )),
+                        other => Err(format!(
+                            "Expected the messages in buffer {} to be records, got {:?}",
+                            buffer, other
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+                single(values, &format!("{} in buffer {}", field, buffer))
+            },
+        )
+    }
+
+    pub fn bind(&self, pair: &ResolvedStatePair) -> Result<TlaValue, String> {
+        (self.extract)(pair).map_err(|e| format!("Can't determine {}: {}", self.description, e))
+    }
+}
+
+/// A parameter of the transition predicate
+#[derive(Clone, Debug)]
+pub enum PredicateParameter {
+    /// A TLA+ expression, used as is
+    Static(String),
+    /// Bound separately for each state pair
+    Derived(ParameterBinding),
+}
+
+impl From<&str> for PredicateParameter {
+    fn from(expression: &str) -> Self {
+        PredicateParameter::Static(expression.to_string())
+    }
+}
+
+impl From<String> for PredicateParameter {
+    fn from(expression: String) -> Self {
+        PredicateParameter::Static(expression)
+    }
+}
+
+impl From<ParameterBinding> for PredicateParameter {
+    fn from(binding: ParameterBinding) -> Self {
+        PredicateParameter::Derived(binding)
+    }
+}
+
+impl PredicateDescription {
+    /// The parameters as TLA+ expressions, with the derived ones bound for the given pair
+    pub fn bind_parameters(&self, pair: &ResolvedStatePair) -> Result<Vec<String>, String> {
+        self.predicate_parameters
+            .iter()
+            .map(|parameter| match parameter {
+                PredicateParameter::Static(expression) => Ok(expression.clone()),
+                PredicateParameter::Derived(binding) => Ok(binding.bind(pair)?.to_string()),
+            })
+            .collect()
+    }
+
+    /// The transition predicate applied to its parameters, as bound for the given pair,
+    /// e.g., `Transfer("alice", 5)`
+    pub fn transition_for(&self, pair: &ResolvedStatePair) -> Result<String, String> {
+        Ok(add_parameters(
+            self.transition_predicate.clone(),
+            self.bind_parameters(pair)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GlobalState, ToTla};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn binds_parameters_per_pair() {
+        let pair = |pid: &str, caller: &str| {
+            let mut start = GlobalState::new();
+            start.add(
+                "pc",
+                BTreeMap::from([(pid.to_string(), "Start".to_string())]).to_tla_value(),
+            );
+            start.add(
+                "ledger_to_wallet",
+                BTreeSet::from([TlaValue::Record(BTreeMap::from([
+                    ("caller".to_string(), caller.to_tla_value()),
+                    ("response".to_string(), 5_u64.to_tla_value()),
+                ]))])
+                .to_tla_value(),
+            );
+            ResolvedStatePair {
+                start,
+                end: GlobalState::new(),
+            }
+        };
+        let predicate = PredicateDescription {
+            tla_module: PathBuf::from("Wallet.tla"),
+            transition_predicate: "Receive".to_string(),
+            predicate_parameters: vec![
+                ParameterBinding::process_id().into(),
+                ParameterBinding::buffer_field("ledger_to_wallet", "caller").into(),
+                "5".into(),
+            ],
+        };
+        assert_eq!(
+            predicate.transition_for(&pair("p1", "p1")).unwrap(),
+            "Receive(\"p1\", \"p1\", 5)"
+        );
+        assert_eq!(
+            predicate.transition_for(&pair("p2", "p3")).unwrap(),
+            "Receive(\"p2\", \"p3\", 5)"
+        );
+        let error = PredicateDescription {
+            predicate_parameters: vec![ParameterBinding::buffer_field("other", "caller").into()],
+            ..predicate
+        }
+        .transition_for(&pair("p1", "p1"))
+        .unwrap_err();
+        assert!(error.starts_with("Can't determine the caller in buffer other"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{parse_failure, ApalacheError, CheckFailure};
+
+/// How often to look whether a child with a timeout has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Counts the free process slots; shared between clones of the same `ProcessLimits`
+#[derive(Debug)]
+struct ProcessSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+struct SlotGuard<'a>(&'a ProcessSlots);
+
+impl ProcessSlots {
+    fn acquire(&self) -> SlotGuard<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        SlotGuard(self)
+    }
+}
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap() += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Limits for the model checker processes. By default, processes run without a time
+/// limit, with the JVM's default heap size, and without a bound on their number.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessLimits {
+    /// Wall-clock time after which a process is killed
+    pub timeout: Option<Duration>,
+    /// The maximum JVM heap size, in the format of `-Xmx`, e.g., `4g`
+    pub jvm_heap: Option<String>,
+    slots: Option<Arc<ProcessSlots>>,
+}
+
+impl ProcessLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_jvm_heap(mut self, jvm_heap: &str) -> Self {
+        self.jvm_heap = Some(jvm_heap.to_string());
+        self
+    }
+
+    /// Bounds the number of processes running at the same time, across all model checkers
+    /// using (clones of) these limits
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.slots = Some(Arc::new(ProcessSlots {
+            free: Mutex::new(max_concurrent.max(1)),
+            freed: Condvar::new(),
+        }));
+        self
+    }
+
+    /// The JVM option for the heap size, if any
+    pub(crate) fn jvm_heap_option(&self) -> Option<String> {
+        self.jvm_heap.as_ref().map(|heap| format!("-Xmx{}", heap))
+    }
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Kills the child and, on Unix, the other processes in its process group. Launchers like
+/// `apalache-mc` are shell scripts that start the JVM as a further process, which would
+/// otherwise keep running.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: `kill` has no memory safety requirements. The child is the leader of its
+    // own process group, which it can't have left, as we haven't reaped it yet.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Runs the command within the limits and turns a non-zero exit status into a `CheckFailure`
+pub(crate) fn run_model_checker(
+    mut cmd: Command,
+    tla_module: &Path,
+    limits: &ProcessLimits,
+) -> Result<(), ApalacheError> {
+    let _slot = limits.slots.as_ref().map(|slots| slots.acquire());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // A process group of its own, such that `kill` reaches the child's children
+        cmd.process_group(0);
+    }
+    let started = Instant::now();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ApalacheError::SetupError(e.to_string()))?;
+    // Drain the pipes concurrently, so that a chatty child doesn't block on a full pipe
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(e) => {
+                kill(&mut child);
+                return Err(ApalacheError::SetupError(e.to_string()));
+            }
+        }
+        if limits.timeout.is_some_and(|t| started.elapsed() >= t) {
+            kill(&mut child);
+            return Err(ApalacheError::Timeout {
+                elapsed: started.elapsed(),
+                module: tla_module.into(),
+            });
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let output = format!(
+        "{}{}",
+        String::from_utf8_lossy(&stdout.join().unwrap_or_default()),
+        String::from_utf8_lossy(&stderr.join().unwrap_or_default())
+    );
+    if status.success() {
+        Ok(())
+    } else {
+        let (kind, excerpt) = parse_failure(&output);
+        Err(ApalacheError::CheckFailed(Box::new(CheckFailure {
+            kind,
+            exit_code: status.code(),
+            module: tla_module.to_path_buf(),
+            excerpt,
+            output,
+        })))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(dir: &Path, name: &str, body: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn kills_processes_after_timeout() {
+        let dir = test_dir("timeout");
+        let slow = script(&dir, "slow", "sleep 10");
+        let limits = ProcessLimits::new().with_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let result = run_model_checker(Command::new(&slow), Path::new("M.tla"), &limits);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match result {
+            Err(ApalacheError::Timeout { elapsed, module }) => {
+                assert!(elapsed >= Duration::from_millis(200));
+                assert_eq!(&*module, Path::new("M.tla"));
+            }
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kills_the_children_of_timed_out_processes() {
+        let dir = test_dir("timeout_group");
+        // Like a launcher script starting the JVM
+        let pid_file = dir.join("pid");
+        let launcher = script(
+            &dir,
+            "launcher",
+            &format!("sleep 10 &\necho $! > {}\nwait", pid_file.display()),
+        );
+        let limits = ProcessLimits::new().with_timeout(Duration::from_millis(500));
+        let result = run_model_checker(Command::new(&launcher), Path::new("M.tla"), &limits);
+        assert!(matches!(result, Err(ApalacheError::Timeout { .. })));
+
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // Killed processes may linger as zombies until their new parent reaps them
+        let running = || {
+            fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        let killed_by = Instant::now() + Duration::from_secs(2);
+        while running() && Instant::now() < killed_by {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert!(
+            !running(),
+            "The child {} of the killed process still runs",
+            pid.trim()
+        );
+    }
+
+    #[test]
+    fn bounds_concurrent_processes() {
+        let dir = test_dir("slots");
+        let log = dir.join("log");
+        let logging = script(
+            &dir,
+            "logging",
+            &format!(
+                "echo start >> {log}\nsleep 0.1\necho end >> {log}",
+                log = log.display()
+            ),
+        );
+        let limits = ProcessLimits::new().with_max_concurrent(1);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    run_model_checker(Command::new(&logging), Path::new("M.tla"), &limits).unwrap()
+                });
+            }
+        });
+        let log = fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start\nend\n".repeat(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::{ApalacheError, BatchReport, PairLocation, PairStatus, TlaCheckError};
+use crate::{GlobalState, UpdateTrace};
+
+/// The outcome of a test case
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaseOutcome {
+    Passed,
+    Failed {
+        /// The kind of error, e.g., `CheckFailed` or `Timeout`
+        kind: String,
+        /// A one-line summary, e.g., the kind of the model checker failure
+        message: String,
+        /// The full error, including the model checker's output excerpt
+        details: String,
+        /// The directory with the artifacts for reproducing the failure, if it was kept
+        bundle: Option<PathBuf>,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+/// A recorded state pair as a test case
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestCase {
+    pub location: PairLocation,
+    /// Groups the cases, e.g., in the CI's test view; the canister's name
+    pub suite: String,
+    /// The canister and update, the index of the pair in its trace, and the `pc` labels of
+    /// the step, e.g., `wallet.transfer #2 (Start_Transfer -> Wait_Reply)`. Without the
+    /// name of the update, the update's start label stands in for it.
+    pub name: String,
+    pub outcome: CaseOutcome,
+    /// The time spent checking; zero for cases that share the result of another case
+    pub duration: Duration,
+}
+
+/// The result of a batch check as test cases, one per recorded state pair, for CI
+/// systems that show test results
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestReport {
+    /// The name of the report's top-level test suite
+    pub name: String,
+    pub cases: Vec<TestCase>,
+    pub duration: Duration,
+}
+
+fn case_name(trace: &UpdateTrace, update_name: Option<&str>, pair_index: usize) -> String {
+    let update = match update_name {
+        Some(name) => format!("{}.{}", trace.update.canister_name, name),
+        None => trace.update.start_label.name().to_string(),
+    };
+    let process_id = &trace.update.process_id;
+    let labels = trace.state_pairs.get(pair_index).map(|pair| {
+        let label = |state: &GlobalState| state.pc_label(process_id).unwrap_or("?").to_string();
+        format!("{} -> {}", label(&pair.start), label(&pair.end))
+    });
+    match labels {
+        Some(labels) => format!("{} #{} ({})", update, pair_index, labels),
+        None => format!("{} #{}", update, pair_index),
+    }
+}
+
+fn failed(error: &TlaCheckError, note: Option<String>) -> CaseOutcome {
+    let (kind, message) = match &error.apalache_error {
+        ApalacheError::CheckFailed(failure) => ("CheckFailed", failure.kind.to_string()),
+        ApalacheError::SetupError(_) => ("SetupError", "setup error".to_string()),
+        ApalacheError::Timeout { elapsed, .. } => {
+            ("Timeout", format!("timed out after {:?}", elapsed))
+        }
+        ApalacheError::DeclarationMismatch(_) => {
+            ("DeclarationMismatch", "declaration mismatch".to_string())
+        }
+    };
+    let mut details = error.apalache_error.to_string();
+    if let Some(localization) = &error.localization {
+        details.push_str(&format!("\n{}", localization));
+    }
+    if let Some(note) = note {
+        details = format!("{}\n{}", note, details);
+    }
+    CaseOutcome::Failed {
+        kind: kind.to_string(),
+        message,
+        details,
+        bundle: error.bundle(),
+    }
+}
+
+fn location_text(location: &PairLocation) -> String {
+    format!(
+        "trace {} pair {}",
+        location.trace_index, location.pair_index
+    )
+}
+
+impl TestReport {
+    /// Builds the report of a batch run on the given traces
+    pub fn new(name: &str, traces: &[UpdateTrace], batch: &BatchReport) -> Self {
+        Self::new_with_update_names(name, traces, &[], batch)
+    }
+
+    /// Like `new`, but names the cases after the updates, whose names are given in the
+    /// order of the traces (e.g., the `update_name`s of `RecordedTrace`s)
+    pub fn new_with_update_names(
+        name: &str,
+        traces: &[UpdateTrace],
+        update_names: &[&str],
+        batch: &BatchReport,
+    ) -> Self {
+        let errors: BTreeMap<PairLocation, &TlaCheckError> = batch
+            .failures
+            .iter()
+            .map(|failure| (failure.location, &failure.error))
+            .collect();
+        let cases = batch
+            .statuses
+            .iter()
+            .map(|(location, status)| {
+                let trace = &traces[location.trace_index];
+                let (outcome, duration) = match status {
+                    PairStatus::Checked(duration) => (
+                        match errors.get(location) {
+                            Some(error) => failed(error, None),
+                            None => CaseOutcome::Passed,
+                        },
+                        *duration,
+                    ),
+                    PairStatus::Stuttering => (
+                        CaseOutcome::Skipped {
+                            reason: "Stuttering step".to_string(),
+                        },
+                        Duration::ZERO,
+                    ),
+                    PairStatus::Duplicate(original) => (
+                        match errors.get(original) {
+                            Some(error) => failed(
+                                error,
+                                Some(format!("Duplicate of {}", location_text(original))),
+                            ),
+                            None => CaseOutcome::Passed,
+                        },
+                        Duration::ZERO,
+                    ),
+                };
+                TestCase {
+                    location: *location,
+                    suite: trace.update.canister_name.clone(),
+                    name: case_name(
+                        trace,
+                        update_names.get(location.trace_index).copied(),
+                        location.pair_index,
+                    ),
+                    outcome,
+                    duration,
+                }
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            cases,
+            duration: batch.elapsed,
+        }
+    }
+
+    fn count(&self, matches: impl Fn(&CaseOutcome) -> bool) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| matches(&case.outcome))
+            .count()
+    }
+
+    pub fn failures(&self) -> usize {
+        self.count(|outcome| matches!(outcome, CaseOutcome::Failed { .. }))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, CaseOutcome::Skipped { .. }))
+    }
+
+    /// The report in the JUnit XML format. Cases are grouped into a test suite per
+    /// canister. Bundles are listed as properties and as Jenkins-style attachments.
+    pub fn to_junit_xml(&self) -> String {
+        let mut suites: BTreeMap<&str, Vec<&TestCase>> = BTreeMap::new();
+        for case in &self.cases {
+            suites.entry(&case.suite).or_default().push(case);
+        }
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(&self.name),
+            self.cases.len(),
+            self.failures(),
+            self.skipped(),
+            self.duration.as_secs_f64()
+        ));
+        for (suite, cases) in suites {
+            let count = |matches: fn(&CaseOutcome) -> bool| {
+                cases.iter().filter(|case| matches(&case.outcome)).count()
+            };
+            xml.push_str(&format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+                xml_escape(suite),
+                cases.len(),
+                count(|o| matches!(o, CaseOutcome::Failed { .. })),
+                count(|o| matches!(o, CaseOutcome::Skipped { .. })),
+                cases.iter().map(|case| case.duration).sum::<Duration>().as_secs_f64()
+            ));
+            for case in cases {
+                xml.push_str(&format!(
+                    "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                    xml_escape(suite),
+                    xml_escape(&case.name),
+                    case.duration.as_secs_f64()
+                ));
+                match &case.outcome {
+                    CaseOutcome::Passed => xml.push_str("/>\n"),
+                    CaseOutcome::Skipped { reason } => xml.push_str(&format!(
+                        ">\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                        xml_escape(reason)
+                    )),
+                    CaseOutcome::Failed {
+                        kind,
+                        message,
+                        details,
+                        bundle,
+                    } => {
+                        xml.push_str(">\n");
+                        if let Some(bundle) = bundle {
+                            let bundle = xml_escape(&bundle.display().to_string());
+                            xml.push_str(&format!(
+                                "      <properties>\n        <property name=\"bundle\" value=\"{}\"/>\n      </properties>\n",
+                                bundle
+                            ));
+                            xml.push_str(&format!(
+                                "      <system-out>[[ATTACHMENT|{}]]</system-out>\n",
+                                bundle
+                            ));
+                        }
+                        xml.push_str(&format!(
+                            "      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>\n",
+                            xml_escape(message),
+                            xml_escape(kind),
+                            xml_escape(details)
+                        ));
+                    }
+                }
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+
+    pub fn to_json(&self) -> Value {
+        let cases: Vec<Value> = self
+            .cases
+            .iter()
+            .map(|case| {
+                let mut value = json!({
+                    "suite": case.suite,
+                    "name": case.name,
+                    "trace": case.location.trace_index,
+                    "pair": case.location.pair_index,
+                    "time": case.duration.as_secs_f64(),
+                });
+                let fields = match &case.outcome {
+                    CaseOutcome::Passed => json!({ "status": "passed" }),
+                    CaseOutcome::Skipped { reason } => {
+                        json!({ "status": "skipped", "reason": reason })
+                    }
+                    CaseOutcome::Failed {
+                        kind,
+                        message,
+                        details,
+                        bundle,
+                    } => json!({
+                        "status": "failed",
+                        "kind": kind,
+                        "message": message,
+                        "details": details,
+                        "bundle": bundle,
+                    }),
+                };
+                if let (Some(value), Value::Object(fields)) = (value.as_object_mut(), fields) {
+                    value.extend(fields);
+                }
+                value
+            })
+            .collect();
+        json!({
+            "name": self.name,
+            "tests": self.cases.len(),
+            "failures": self.failures(),
+            "skipped": self.skipped(),
+            "time": self.duration.as_secs_f64(),
+            "cases": cases,
+        })
+    }
+
+    pub fn write_junit_xml(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_junit_xml())
+            .map_err(|e| format!("Can't write the report {}: {}", path.display(), e))
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(&self.to_json()).map_err(|e| e.to_string())?;
+        fs::write(path, text)
+            .map_err(|e| format!("Can't write the report {}: {}", path.display(), e))
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::PredicateDescription;
+    use crate::checker::{check_traces, BatchOptions, FailureKind, FakeChecker, Verdict};
+    use crate::test_helpers::test_dir;
+    use crate::{ResolvedStatePair, ToTla, Update};
+
+    #[test]
+    fn reports_each_pair() {
+        let dir = test_dir("report");
+        let module = dir.join("Counter.tla");
+        fs::write(
+            &module,
+            "---- MODULE Counter ----\nVARIABLES counter, pc\n====\n",
+        )
+        .unwrap();
+        let state = |counter: u64, label: &str| {
+            let mut state = GlobalState::new();
+            state.add("counter", counter.to_tla_value());
+            state.add(
+                "pc",
+                BTreeMap::from([("pid".to_string(), label.to_string())]).to_tla_value(),
+            );
+            state
+        };
+        let pair = |start: u64, end: u64| ResolvedStatePair {
+            start: state(start, "Start_Increment"),
+            end: state(end, "Done"),
+        };
+        let trace = |state_pairs| {
+            crate::test_helpers::trace(
+                Update::new("Start_Increment", "Done", "pid", "counter"),
+                state_pairs,
+            )
+        };
+        let predicate = PredicateDescription {
+            tla_module: module,
+            transition_predicate: "Next".to_string(),
+            predicate_parameters: vec![],
+        };
+        let checker =
+            FakeChecker::new().when_contains("counter' = 3", Verdict::Fail(FailureKind::Deadlock));
+        // Two updates with the same start label
+        let traces = vec![
+            trace(vec![
+                pair(0, 1),
+                ResolvedStatePair {
+                    start: state(1, "Done"),
+                    end: state(1, "Done"),
+                },
+                pair(1, 3),
+                pair(0, 1),
+            ]),
+            trace(vec![pair(5, 6)]),
+        ];
+        let batch = check_traces(&checker, &predicate, &traces, &BatchOptions::default());
+        let report = TestReport::new_with_update_names(
+            "code <-> spec",
+            &traces,
+            &["increment", "increment_twice"],
+            &batch,
+        );
+        let unnamed = TestReport::new("code <-> spec", &traces, &batch);
+        for bundle in report.cases.iter().filter_map(|case| match &case.outcome {
+            CaseOutcome::Failed { bundle, .. } => bundle.clone(),
+            _ => None,
+        }) {
+            let _ = fs::remove_dir_all(bundle);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.cases.len(), 5);
+        assert_eq!(report.failures(), 1);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(
+            report.cases[2].name,
+            "counter.increment #2 (Start_Increment -> Done)"
+        );
+        assert_eq!(
+            report.cases[4].name,
+            "counter.increment_twice #0 (Start_Increment -> Done)"
+        );
+        assert_eq!(
+            unnamed.cases[2].name,
+            "Start_Increment #2 (Start_Increment -> Done)"
+        );
+        assert_eq!(report.cases[3].outcome, CaseOutcome::Passed);
+        let xml = report.to_junit_xml();
+        assert!(xml.contains(
+            "<testsuites name=\"code &lt;-&gt; spec\" tests=\"5\" failures=\"1\" skipped=\"1\""
+        ));
+        assert!(xml.contains(
+            "<failure message=\"the transition is not enabled (deadlock)\" type=\"CheckFailed\">"
+        ));
+        assert!(xml.contains("<skipped message=\"Stuttering step\"/>"));
+        let json = report.to_json();
+        assert_eq!(json["cases"][2]["status"], "failed");
+        assert_eq!(json["cases"][1]["reason"], "Stuttering step");
+        assert_eq!(json["failures"], 1);
+    }
+}
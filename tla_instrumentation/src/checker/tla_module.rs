@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(stripped) = rest.strip_prefix("(*") {
+            rest = stripped
+                .find("*)")
+                .map(|i| &stripped[i + 2..])
+                .unwrap_or("");
+            result.push(' ');
+        } else if let Some(stripped) = rest.strip_prefix("\\*") {
+            rest = stripped.find('\n').map(|i| &stripped[i..]).unwrap_or("");
+        } else {
+            let c = rest.chars().next().unwrap();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    result
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the comma-separated identifiers that follow each occurrence of the keyword,
+/// e.g., the module names following `EXTENDS`. The list may span multiple lines.
+pub(crate) fn keyword_arguments(text: &str, keyword: &str) -> Vec<String> {
+    let text = strip_comments(text);
+    let mut arguments = Vec::new();
+    let mut search_from = 0;
+    while let Some(pos) = text[search_from..].find(keyword) {
+        let start = search_from + pos;
+        let end = start + keyword.len();
+        search_from = end;
+        let preceded_by_identifier = text[..start].chars().last().is_some_and(is_identifier_char);
+        let followed_by_identifier = text[end..].chars().next().is_some_and(is_identifier_char);
+        if preceded_by_identifier || followed_by_identifier {
+            continue;
+        }
+        let mut rest = &text[end..];
+        loop {
+            rest = rest.trim_start();
+            let name: String = rest
+                .chars()
+                .take_while(|c| is_identifier_char(*c))
+                .collect();
+            if name.is_empty() {
+                break;
+            }
+            rest = rest[name.len()..].trim_start();
+            arguments.push(name);
+            match rest.strip_prefix(',') {
+                Some(after_comma) => rest = after_comma,
+                None => break,
+            }
+        }
+    }
+    arguments
+}
+
+/// The names of the modules that the module text extends or instantiates
+pub(crate) fn imported_modules(text: &str) -> Vec<String> {
+    let mut imports = keyword_arguments(text, "EXTENDS");
+    imports.extend(keyword_arguments(text, "INSTANCE"));
+    imports
+}
+
+/// Returns the given module file, followed by the files of all modules that it
+/// (transitively) imports and that exist next to it. Imports without such a file, e.g.,
+/// the standard modules, are skipped.
+pub(crate) fn module_closure(tla_module: &Path) -> Result<Vec<PathBuf>, String> {
+    closure(tla_module, imported_modules)
+}
+
+/// Like `module_closure`, but only follows `EXTENDS`, i.e., returns the modules whose
+/// declarations become part of the given module
+pub(crate) fn extends_closure(tla_module: &Path) -> Result<Vec<PathBuf>, String> {
+    closure(tla_module, |text| keyword_arguments(text, "EXTENDS"))
+}
+
+fn closure(tla_module: &Path, imports: fn(&str) -> Vec<String>) -> Result<Vec<PathBuf>, String> {
+    let dir = tla_module.parent().unwrap_or(Path::new("."));
+    let mut seen = BTreeSet::new();
+    let mut files = Vec::new();
+    let mut queue = vec![tla_module.to_path_buf()];
+    while let Some(file) = queue.pop() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+        for import in imports(&text) {
+            let import_file = dir.join(format!("{}.tla", import));
+            if import_file.exists() {
+                queue.push(import_file);
+            }
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
+
+/// The constants declared (with `CONSTANT` or `CONSTANTS`) by the module or the modules
+/// it imports from its directory
+pub(crate) fn declared_constants(tla_module: &Path) -> Result<BTreeSet<String>, String> {
+    let mut constants = BTreeSet::new();
+    for file in module_closure(tla_module)? {
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+        constants.extend(keyword_arguments(&text, "CONSTANT"));
+        constants.extend(keyword_arguments(&text, "CONSTANTS"));
+    }
+    Ok(constants)
+}
+
+/// The variables and constants that a module declares itself or through the modules it
+/// extends
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpecDeclarations {
+    pub variables: BTreeSet<String>,
+    pub constants: BTreeSet<String>,
+}
+
+pub fn spec_declarations(tla_module: &Path) -> Result<SpecDeclarations, String> {
+    let mut declarations = SpecDeclarations::default();
+    for file in extends_closure(tla_module)? {
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+        for keyword in ["VARIABLE", "VARIABLES"] {
+            declarations
+                .variables
+                .extend(keyword_arguments(&text, keyword));
+        }
+        for keyword in ["CONSTANT", "CONSTANTS"] {
+            declarations
+                .constants
+                .extend(keyword_arguments(&text, keyword));
+        }
+    }
+    Ok(declarations)
+}
+
+/// Whether the text uses the identifier, e.g., `pc`, rather than just containing it
+fn mentions(text: &str, identifier: &str) -> bool {
+    text.match_indices(identifier).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + identifier.len()..].chars().next();
+        !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+    })
+}
+
+/// The PlusCal labels of the spec, i.e., the strings that the module (or a module it
+/// extends) assigns to `pc` or compares it with. The translation of a PlusCal algorithm
+/// does so on lines like `/\ pc[self] = "Start"` and
+/// `/\ pc' = [pc EXCEPT ![self] = "Done"]`, which are the lines considered here.
+pub fn spec_labels(tla_module: &Path) -> Result<BTreeSet<String>, String> {
+    let mut labels = BTreeSet::new();
+    for file in extends_closure(tla_module)? {
+        let text = fs::read_to_string(&file)
+            .map_err(|e| format!("Couldn't read from module {}: {}", file.display(), e))?;
+        for line in strip_comments(&text)
+            .lines()
+            .filter(|line| mentions(line, "pc"))
+        {
+            // The odd pieces of the split are the string literals
+            let pieces: Vec<&str> = line.split('"').collect();
+            for i in (1..pieces.len() - 1).step_by(2) {
+                let before = pieces[i - 1].trim_end();
+                if before.ends_with('=') || before.ends_with('#') || before.ends_with("|->") {
+                    labels.insert(pieces[i].to_string());
+                }
+            }
+        }
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+
+    #[test]
+    fn finds_imports() {
+        let text = "\
+---- MODULE Spec ----
+EXTENDS Integers, (* a comment *) Sequences,
+    Common
+\\* EXTENDS Commented
+Ledger == INSTANCE Ledger WITH balance <- ledger_balance
+NOT_EXTENDS_X == 1
+====";
+        assert_eq!(
+            imported_modules(text),
+            vec!["Integers", "Sequences", "Common", "Ledger"]
+        );
+    }
+
+    #[test]
+    fn finds_labels() {
+        let dir = test_dir("labels");
+        let module = dir.join("Wallet.tla");
+        fs::write(
+            &module,
+            "---- MODULE Wallet ----\n\
+             (* --algorithm Wallet { Start_Transfer: skip; } *)\n\
+             VARIABLES pc, npc\n\
+             Init == pc = [self \\in PIDS |-> \"Start_Transfer\"]\n\
+             Start_Transfer(self) == /\\ pc[self] = \"Start_Transfer\"\n\
+             \x20   /\\ pc' = [pc EXCEPT ![self] = \"Wait_Reply\"]\n\
+             \x20   /\\ npc' = \"Not_A_Label\"\n\
+             Wait_Reply(self) == pc[self] # \"Done\" /\\ UNCHANGED npc\n\
+             ====\n",
+        )
+        .unwrap();
+        let labels = spec_labels(&module);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            labels.unwrap(),
+            BTreeSet::from([
+                "Done".to_string(),
+                "Start_Transfer".to_string(),
+                "Wait_Reply".to_string(),
+            ])
+        );
+    }
+}
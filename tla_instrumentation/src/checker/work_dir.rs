@@ -0,0 +1,247 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::tla_module::module_closure;
+use super::ApalacheError;
+
+/// Environment variable holding the directory under which the working directories of
+/// failed checks are kept
+pub const ARTIFACTS_DIR_ENV_VAR: &str = "TLA_CHECK_ARTIFACTS_DIR";
+
+/// The directory under which the working directories of failed checks are kept: the value
+/// of `TLA_CHECK_ARTIFACTS_DIR` if set, and `tla_code_link_artifacts` in the system's
+/// temporary directory otherwise
+pub fn artifacts_dir() -> PathBuf {
+    std::env::var_os(ARTIFACTS_DIR_ENV_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("tla_code_link_artifacts"))
+}
+
+static WORK_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh temporary directory for a single check, which references the spec and the
+/// modules it imports, so that the generated module and the model checker's output don't
+/// end up next to the user's spec
+pub(crate) struct WorkDir {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+fn link(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+fn link(original: &Path, link: &Path) -> io::Result<()> {
+    fs::copy(original, link).map(|_| ())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        // Follows symbolic links, such that the copy is self-contained
+        if fs::metadata(entry.path())?.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the symbolic links in the directory by copies of the files they point to
+fn materialize_links(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            let target = fs::read_link(&path)?;
+            fs::remove_file(&path)?;
+            fs::copy(target, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates a new directory under `parent` named after `name`, adding a suffix if a
+/// directory of that name exists, e.g., from an earlier run with the same process ID
+fn create_fresh_dir(parent: &Path, name: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(parent)?;
+    for attempt in 0.. {
+        let path = match attempt {
+            0 => parent.join(name),
+            n => parent.join(format!("{}_{}", name, n)),
+        };
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("The attempts are unbounded")
+}
+
+/// Points the paths in the error to the kept copy of the working directory
+fn relocate(error: ApalacheError, from: &Path, to: &Path) -> ApalacheError {
+    let move_path = |path: &Path| {
+        path.strip_prefix(from)
+            .map(|rest| to.join(rest))
+            .unwrap_or(path.to_path_buf())
+    };
+    match error {
+        ApalacheError::CheckFailed(mut failure) => {
+            failure.module = move_path(&failure.module);
+            ApalacheError::CheckFailed(failure)
+        }
+        ApalacheError::Timeout { elapsed, module } => ApalacheError::Timeout {
+            elapsed,
+            module: move_path(&module).into(),
+        },
+        e => e,
+    }
+}
+
+impl WorkDir {
+    pub(crate) fn new(tla_module: &Path) -> Result<Self, String> {
+        let tla_module = fs::canonicalize(tla_module)
+            .map_err(|e| format!("Can't find the module {}: {}", tla_module.display(), e))?;
+        let path = std::env::temp_dir().join(format!(
+            "tla_code_link_{}_{}",
+            std::process::id(),
+            WORK_DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).map_err(|e| {
+            format!(
+                "Can't create the working directory {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let work_dir = Self { path };
+        for file in module_closure(&tla_module)? {
+            let file_name = file.file_name().expect("Module files have names");
+            link(&file, &work_dir.path.join(file_name))
+                .map_err(|e| format!("Can't reference {}: {}", file.display(), e))?;
+        }
+        Ok(work_dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes the directory if the check passed. Otherwise, keeps it under the artifacts
+    /// directory, and points the error there.
+    pub(crate) fn finish(
+        self,
+        result: Result<(), ApalacheError>,
+        artifacts_dir: &Path,
+    ) -> Result<(), ApalacheError> {
+        let Err(error) = result else {
+            let _ = fs::remove_dir_all(&self.path);
+            return Ok(());
+        };
+        if matches!(
+            error,
+            ApalacheError::SetupError(_) | ApalacheError::DeclarationMismatch(_)
+        ) {
+            let _ = fs::remove_dir_all(&self.path);
+            return Err(error);
+        }
+        let name = self.path.file_name().expect("Work dirs have names");
+        let moved = create_fresh_dir(artifacts_dir, &name.to_string_lossy()).and_then(|kept| {
+            fs::rename(&self.path, &kept)
+                // Renaming fails across file systems
+                .or_else(|_| {
+                    copy_dir(&self.path, &kept).and_then(|_| fs::remove_dir_all(&self.path))
+                })
+                .map(|()| kept)
+        });
+        match moved {
+            Ok(kept) => {
+                // Make the kept directory self-contained, in case the spec changes later
+                let _ = materialize_links(&kept);
+                Err(relocate(error, &self.path, &kept))
+            }
+            // Leave everything where it is; the error points there already
+            Err(_) => Err(error),
+        }
+    }
+}
+
+/// Removes the kept working directory of a failed check, for failures that are expected
+pub(crate) fn discard_artifacts(error: &ApalacheError) {
+    let module = match error {
+        ApalacheError::CheckFailed(failure) => failure.module.as_path(),
+        ApalacheError::Timeout { module, .. } => module,
+        ApalacheError::SetupError(_) | ApalacheError::DeclarationMismatch(_) => return,
+    };
+    if let Some(dir) = module.parent() {
+        if dir.starts_with(artifacts_dir()) {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::{CheckFailure, FailureKind};
+    use crate::test_helpers::test_dir;
+
+    #[test]
+    fn keeps_only_failed_runs() {
+        let dir = test_dir("work_dir");
+        let spec_dir = dir.join("spec");
+        let artifacts = dir.join("artifacts");
+        fs::create_dir_all(&spec_dir).unwrap();
+        let module = spec_dir.join("Counter.tla");
+        fs::write(&module, "---- MODULE Counter ----\nEXTENDS Base\n====\n").unwrap();
+        fs::write(spec_dir.join("Base.tla"), "---- MODULE Base ----\n====\n").unwrap();
+
+        let passing = WorkDir::new(&module).unwrap();
+        let passing_path = passing.path().to_path_buf();
+        assert!(passing_path.join("Counter.tla").exists());
+        assert!(passing_path.join("Base.tla").exists());
+        passing.finish(Ok(()), &artifacts).unwrap();
+        assert!(!passing_path.exists());
+
+        let failing = WorkDir::new(&module).unwrap();
+        let generated = failing.path().join("Generated.tla");
+        fs::write(&generated, "generated").unwrap();
+        // Left by an earlier run whose process had the same ID
+        let earlier = artifacts.join(failing.path().file_name().unwrap());
+        fs::create_dir_all(&earlier).unwrap();
+        fs::write(earlier.join("Generated.tla"), "earlier").unwrap();
+        let error = failing
+            .finish(
+                Err(ApalacheError::CheckFailed(Box::new(CheckFailure {
+                    kind: FailureKind::Deadlock,
+                    exit_code: Some(12),
+                    module: generated,
+                    excerpt: String::new(),
+                    output: String::new(),
+                }))),
+                &artifacts,
+            )
+            .unwrap_err();
+        let ApalacheError::CheckFailed(failure) = error else {
+            panic!("Unexpected error {:?}", error);
+        };
+        assert!(failure.module.starts_with(&artifacts));
+        assert_eq!(fs::read_to_string(&failure.module).unwrap(), "generated");
+        assert!(failure.module.with_file_name("Base.tla").exists());
+        assert!(!failure.module.starts_with(&earlier));
+        assert_eq!(
+            fs::read_to_string(earlier.join("Generated.tla")).unwrap(),
+            "earlier"
+        );
+        // The spec is left alone
+        assert_eq!(fs::read_dir(&spec_dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
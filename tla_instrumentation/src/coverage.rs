@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crate::checker::spec_labels;
+use crate::{ResolvedStatePair, TlaValue, UpdateTrace};
+
+/// Which parts of the spec a set of traces exercises, with the number of state pairs
+/// in which each part occurs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// The `pc` labels of the processes in the pre-states
+    pub start_labels: BTreeMap<String, usize>,
+    /// The `pc` labels of the processes in the post-states
+    pub end_labels: BTreeMap<String, usize>,
+    /// The steps from one label to another
+    pub transitions: BTreeMap<(String, String), usize>,
+    /// The destinations and methods of the requests sent by the processes of the traces
+    pub requests: BTreeMap<(String, String), usize>,
+}
+
+/// The destinations and methods of the requests that the process sent in the step. The
+/// requests are in the `<canister>_to_<destination>` buffers, as records with the
+/// `caller` and a `method_and_args` variant. The buffers are FIFO queues, so the requests
+/// sent earlier are the longest suffix of the pre-state buffer that starts the post-state
+/// buffer; the requests after them are new.
+fn sent_requests<'a>(
+    pair: &'a ResolvedStatePair,
+    canister_name: &str,
+    process_id: &str,
+) -> Vec<(&'a str, &'a str)> {
+    let prefix = format!("{}_to_", canister_name);
+    let mut requests = Vec::new();
+    for (name, value) in &pair.end.0 .0 {
+        let (Some(destination), TlaValue::Seq(messages)) = (name.strip_prefix(&prefix), value)
+        else {
+            continue;
+        };
+        let earlier: &[TlaValue] = match pair.start.get(name) {
+            Some(TlaValue::Seq(messages)) => messages,
+            _ => &[],
+        };
+        let consumed = (0..=earlier.len())
+            .find(|&i| messages.starts_with(&earlier[i..]))
+            .unwrap_or(earlier.len());
+        for message in &messages[earlier.len() - consumed..] {
+            let TlaValue::Record(fields) = message else {
+                continue;
+            };
+            if !matches!(fields.get("caller"), Some(TlaValue::Literal(caller)) if caller == process_id)
+            {
+                continue;
+            }
+            if let Some(TlaValue::Variant { tag, .. }) = fields.get("method_and_args") {
+                requests.push((destination, tag.as_str()));
+            }
+        }
+    }
+    requests
+}
+
+impl Coverage {
+    pub fn from_traces(traces: &[UpdateTrace]) -> Self {
+        let mut coverage = Self::default();
+        for trace in traces {
+            let process_id = &trace.update.process_id;
+            for pair in &trace.state_pairs {
+                let start = pair.start.pc_label(process_id);
+                let end = pair.end.pc_label(process_id);
+                if let Some(start) = start {
+                    *coverage.start_labels.entry(start.to_string()).or_default() += 1;
+                }
+                if let Some(end) = end {
+                    *coverage.end_labels.entry(end.to_string()).or_default() += 1;
+                }
+                if let (Some(start), Some(end)) = (start, end) {
+                    *coverage
+                        .transitions
+                        .entry((start.to_string(), end.to_string()))
+                        .or_default() += 1;
+                }
+                for (destination, method) in
+                    sent_requests(pair, &trace.update.canister_name, process_id)
+                {
+                    *coverage
+                        .requests
+                        .entry((destination.to_string(), method.to_string()))
+                        .or_default() += 1;
+                }
+            }
+        }
+        coverage
+    }
+
+    /// The labels observed as a start or an end label
+    pub fn reached_labels(&self) -> BTreeSet<&str> {
+        self.start_labels
+            .keys()
+            .chain(self.end_labels.keys())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The declared labels that no trace reached
+    pub fn unreached_labels(&self, declared: &BTreeSet<String>) -> Vec<String> {
+        let reached = self.reached_labels();
+        declared
+            .iter()
+            .filter(|label| !reached.contains(label.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// The labels of the spec (see `spec_labels`) that no trace reached
+    pub fn unreached_spec_labels(&self, tla_module: &Path) -> Result<Vec<String>, String> {
+        Ok(self.unreached_labels(&spec_labels(tla_module)?))
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Start labels:")?;
+        for (label, count) in &self.start_labels {
+            writeln!(f, "  {} ({})", label, count)?;
+        }
+        writeln!(f, "End labels:")?;
+        for (label, count) in &self.end_labels {
+            writeln!(f, "  {} ({})", label, count)?;
+        }
+        writeln!(f, "Transitions:")?;
+        for ((start, end), count) in &self.transitions {
+            writeln!(f, "  {} -> {} ({})", start, end, count)?;
+        }
+        writeln!(f, "Requests:")?;
+        for ((destination, method), count) in &self.requests {
+            writeln!(f, "  {}.{} ({})", destination, method, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::trace;
+    use crate::{GlobalState, ResolvedStatePair, ToTla, Update};
+
+    /// The requests are pairs of the caller and the method
+    fn state(label: &str, requests: Vec<(&str, &str)>) -> GlobalState {
+        let mut state = GlobalState::new();
+        state.add(
+            "pc",
+            BTreeMap::from([("wallet_1".to_string(), label.to_string())]).to_tla_value(),
+        );
+        state.add(
+            "wallet_to_ledger",
+            TlaValue::Seq(
+                requests
+                    .into_iter()
+                    .map(|(caller, method)| {
+                        TlaValue::Record(BTreeMap::from([
+                            ("caller".to_string(), caller.to_tla_value()),
+                            (
+                                "method_and_args".to_string(),
+                                TlaValue::Variant {
+                                    tag: method.to_string(),
+                                    value: Box::new(1_u64.to_tla_value()),
+                                },
+                            ),
+                        ]))
+                    })
+                    .collect(),
+            ),
+        );
+        state
+    }
+
+    #[test]
+    fn collects_labels_and_requests() {
+        let pair = |start: GlobalState, end: GlobalState| ResolvedStatePair { start, end };
+        let trace = trace(
+            Update::new("Start_Transfer", "Done", "wallet_1", "wallet"),
+            vec![
+                pair(
+                    state("Start_Transfer", vec![("wallet_2", "balance")]),
+                    state(
+                        "Wait_Reply",
+                        vec![
+                            ("wallet_2", "balance"),
+                            ("wallet_1", "transfer"),
+                            ("wallet_2", "transfer"),
+                        ],
+                    ),
+                ),
+                // The request is still buffered, but was sent in the previous step
+                pair(
+                    state("Wait_Reply", vec![("wallet_1", "transfer")]),
+                    state("Done", vec![("wallet_1", "transfer")]),
+                ),
+                // The earlier transfer was consumed, and an identical one sent again
+                pair(
+                    state(
+                        "Start_Transfer",
+                        vec![("wallet_1", "transfer"), ("wallet_2", "balance")],
+                    ),
+                    state(
+                        "Wait_Reply",
+                        vec![("wallet_2", "balance"), ("wallet_1", "transfer")],
+                    ),
+                ),
+            ],
+        );
+
+        let coverage = Coverage::from_traces(&[trace]);
+
+        assert_eq!(
+            coverage.transitions.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                ("Start_Transfer".to_string(), "Wait_Reply".to_string()),
+                ("Wait_Reply".to_string(), "Done".to_string()),
+            ]
+        );
+        assert_eq!(coverage.start_labels["Wait_Reply"], 1);
+        assert_eq!(coverage.end_labels["Wait_Reply"], 2);
+        assert_eq!(coverage.end_labels["Done"], 1);
+        assert_eq!(
+            coverage.requests,
+            BTreeMap::from([(("ledger".to_string(), "transfer".to_string()), 2)])
+        );
+        let declared = BTreeSet::from([
+            "Start_Transfer".to_string(),
+            "Refund".to_string(),
+            "Done".to_string(),
+        ]);
+        assert_eq!(coverage.unreached_labels(&declared), vec!["Refund"]);
+        assert!(coverage
+            .to_string()
+            .contains("Start_Transfer -> Wait_Reply (2)"));
+    }
+}
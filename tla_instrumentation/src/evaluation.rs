@@ -0,0 +1,1445 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use candid::Nat;
+
+use crate::{GlobalState, TlaConstantAssignment, TlaValue, UpdateTrace};
+
+/// The largest set that an expression may enumerate, e.g., with `1..N`
+const MAX_SET_SIZE: i128 = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Int(i128),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(i) => write!(f, "{}", i),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// The symbols, longest first, so that the longest match wins
+const SYMBOLS: &[&str] = &[
+    "<=>", "|->", "=>", "->", "=<", "<=", ">=", "/=", "/\\", "\\/", "<<", ">>", "..", ":>", "@@",
+    "(", ")", "[", "]", "{", "}", ",", ":", ".", "=", "#", "<", ">", "+", "-", "*", "%", "~", "!",
+    "@",
+];
+
+/// The operators written as a backslash followed by a word, with their canonical names
+const WORD_OPERATORS: &[(&str, &str)] = &[
+    ("in", "\\in"),
+    ("notin", "\\notin"),
+    ("subseteq", "\\subseteq"),
+    ("union", "\\union"),
+    ("cup", "\\union"),
+    ("intersect", "\\intersect"),
+    ("cap", "\\intersect"),
+    ("div", "\\div"),
+    ("o", "\\o"),
+    ("A", "\\A"),
+    ("E", "\\E"),
+    ("lnot", "~"),
+    ("neg", "~"),
+    ("land", "/\\"),
+    ("lor", "\\/"),
+    ("leq", "<="),
+    ("geq", ">="),
+];
+
+/// The tokens with their columns, which delimit the items of conjunction and disjunction
+/// lists
+fn lex(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let offset = text.len() - rest.len();
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let column = text[line_start..offset].chars().count();
+        let mut push = |token| tokens.push((token, column));
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(comment) = rest.strip_prefix("\\*") {
+            rest = comment.find('\n').map(|i| &comment[i..]).unwrap_or("");
+        } else if let Some(comment) = rest.strip_prefix("(*") {
+            let end = comment.find("*)").ok_or("Unterminated comment")?;
+            rest = &comment[end + 2..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| format!("Integer {} is too large", &rest[..end]))?;
+            push(Token::Int(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(string) = rest.strip_prefix('"') {
+            let end = string.find('"').ok_or("Unterminated string")?;
+            push(Token::Str(string[..end].to_string()));
+            rest = &string[end + 1..];
+        } else if let Some(word) = rest
+            .strip_prefix('\\')
+            .filter(|word| word.starts_with(|c: char| c.is_ascii_alphabetic()))
+        {
+            let end = word
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(word.len());
+            let op = WORD_OPERATORS
+                .iter()
+                .find(|(name, _)| *name == &word[..end])
+                .map(|(_, op)| *op)
+                .ok_or(format!("Unsupported operator \\{}", &word[..end]))?;
+            push(Token::Op(op));
+            rest = &word[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            push(Token::Op(symbol));
+            rest = &rest[symbol.len()..];
+        } else if let Some(after) = rest.strip_prefix('\\') {
+            // Set difference
+            push(Token::Op("\\"));
+            rest = after;
+        } else {
+            return Err(format!("Unexpected character {}", c));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum ExceptKey {
+    Index(Vec<Expr>),
+    Field(String),
+}
+
+/// The name that `@` is bound to in the new values of an `EXCEPT`
+const EXCEPT_OLD_VALUE: &str = "@";
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Int(i128),
+    Str(String),
+    Bool(bool),
+    Ident(String),
+    /// `~`, `-`, `DOMAIN` or `UNION`
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `f[a]`, or `f[a, b]` for a function of a tuple
+    Apply(Box<Expr>, Vec<Expr>),
+    Field(Box<Expr>, String),
+    /// A standard operator, e.g., `Cardinality(S)`
+    Call(String, Vec<Expr>),
+    SetEnum(Vec<Expr>),
+    /// `{x \in S : P}`
+    SetFilter(String, Box<Expr>, Box<Expr>),
+    /// `{e : x \in S}`
+    SetMap(Box<Expr>, Vec<(String, Expr)>),
+    Tuple(Vec<Expr>),
+    Record(Vec<(String, Expr)>),
+    /// `[x \in S |-> e]`
+    Function(Vec<(String, Expr)>, Box<Expr>),
+    /// `[f EXCEPT ![a][b] = e, !.c = e]`, with a path of keys for each update
+    Except(Box<Expr>, Vec<(Vec<ExceptKey>, Expr)>),
+    /// `[S -> T]`, which can only be used with `\in`
+    FunctionSet(Box<Expr>, Box<Expr>),
+    /// `[a : S, b : T]`, which can only be used with `\in`
+    RecordSet(Vec<(String, Expr)>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `\A` (if the flag is set) or `\E`
+    Quantifier(bool, Vec<(String, Expr)>, Box<Expr>),
+    Choose(String, Box<Expr>, Box<Expr>),
+}
+
+/// The binding powers of the infix operators, loosely following the TLA+ precedences.
+/// Operators are left associative, except for the ones in `NON_ASSOCIATIVE`.
+fn infix_power(op: &str) -> Option<(u8, u8)> {
+    let left = match op {
+        "=>" => 1,
+        "<=>" => 2,
+        "\\/" => 3,
+        "/\\" => 4,
+        "=" | "#" | "/=" | "<" | ">" | "<=" | "=<" | ">=" | "\\in" | "\\notin" | "\\subseteq" => 6,
+        "@@" => 7,
+        ":>" => 8,
+        "\\union" | "\\intersect" | "\\" => 9,
+        ".." => 10,
+        "+" | "-" | "%" => 11,
+        "*" | "\\div" | "\\o" => 13,
+        _ => return None,
+    };
+    Some((left, left + 1))
+}
+
+/// As in TLA+, chains of these operators need parentheses
+const NON_ASSOCIATIVE: &[&str] = &["=>", "<=>"];
+
+/// Binds tighter than any infix operator
+const POSTFIX_POWER: u8 = 20;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// The columns of the bullets of the enclosing list items. An item ends before the
+    /// first token that isn't to the right of its bullet.
+    bullet_columns: Vec<usize>,
+    /// The number of enclosing `EXCEPT` values, in which `@` stands for the old value
+    except_values: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self, String> {
+        Ok(Self {
+            tokens: lex(text)?,
+            position: 0,
+            bullet_columns: Vec::new(),
+            except_values: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        let (token, column) = self.tokens.get(self.position + offset)?;
+        match self.bullet_columns.last() {
+            Some(bullet_column) if column <= bullet_column => None,
+            _ => Some(token),
+        }
+    }
+
+    /// A conjunction or disjunction list, after its first bullet. The items of the list
+    /// start with the same bullet in the same column.
+    fn junction_list(&mut self, bullet: &'static str) -> Result<Expr, String> {
+        let column = self.tokens[self.position - 1].1;
+        let mut items = Vec::new();
+        loop {
+            self.bullet_columns.push(column);
+            let item = self.expr(0);
+            self.bullet_columns.pop();
+            items.push(item?);
+            match self.tokens.get(self.position) {
+                Some((Token::Op(op), c)) if *op == bullet && *c == column => self.position += 1,
+                _ => break,
+            }
+        }
+        Ok(items
+            .into_iter()
+            .reduce(|lhs, rhs| Expr::Binary(bullet, Box::new(lhs), Box::new(rhs)))
+            .expect("A list has at least one item"))
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Token::Op(o)) if *o == op)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned().ok_or("Unexpected end of expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), String> {
+        match self.next()? {
+            Token::Op(o) if o == op => Ok(()),
+            other => Err(format!("Expected {}, found {}", op, other)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.next()? {
+            Token::Ident(name) if name == keyword => Ok(()),
+            other => Err(format!("Expected {}, found {}", keyword, other)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Ident(name) => Ok(name),
+            other => Err(format!("Expected an identifier, found {}", other)),
+        }
+    }
+
+    /// Comma-separated expressions, up to the closing symbol
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>, String> {
+        let mut elements = Vec::new();
+        if self.is_op(close) {
+            self.next()?;
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.expr(0)?);
+            match self.next()? {
+                Token::Op(",") => continue,
+                Token::Op(op) if op == close => return Ok(elements),
+                other => return Err(format!("Expected , or {}, found {}", close, other)),
+            }
+        }
+    }
+
+    /// Bounds like `x \in S, y, z \in T`
+    fn bounds(&mut self) -> Result<Vec<(String, Expr)>, String> {
+        let mut bounds = Vec::new();
+        loop {
+            let mut names = vec![self.ident()?];
+            while self.is_op(",") {
+                self.next()?;
+                names.push(self.ident()?);
+            }
+            self.expect_op("\\in")?;
+            let set = self.expr(infix_power("\\in").unwrap().1)?;
+            bounds.extend(names.into_iter().map(|name| (name, set.clone())));
+            if !self.is_op(",") {
+                return Ok(bounds);
+            }
+            self.next()?;
+        }
+    }
+
+    fn expr(&mut self, min_power: u8) -> Result<Expr, String> {
+        let mut lhs = self.prefix()?;
+        // The infix operator that produced `lhs`, unless it's parenthesized
+        let mut previous = None;
+        while let Some(&Token::Op(op)) = self.peek() {
+            if op == "[" || op == "." {
+                if min_power > POSTFIX_POWER {
+                    break;
+                }
+                self.next()?;
+                lhs = if op == "[" {
+                    Expr::Apply(Box::new(lhs), self.list("]")?)
+                } else {
+                    Expr::Field(Box::new(lhs), self.ident()?)
+                };
+                continue;
+            }
+            let Some((left, right)) = infix_power(op) else {
+                break;
+            };
+            if left < min_power {
+                break;
+            }
+            if NON_ASSOCIATIVE.contains(&op) && previous == Some(op) {
+                return Err(format!("{} is not associative; add parentheses", op));
+            }
+            self.next()?;
+            let rhs = self.expr(right)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            previous = Some(op);
+        }
+        Ok(lhs)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            Token::Int(i) => Ok(Expr::Int(i)),
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Op(bullet @ ("/\\" | "\\/")) => self.junction_list(bullet),
+            Token::Op("~") => Ok(Expr::Unary("~", Box::new(self.expr(5)?))),
+            Token::Op("-") => Ok(Expr::Unary("-", Box::new(self.expr(12)?))),
+            Token::Op("(") => {
+                let expr = self.expr(0)?;
+                self.expect_op(")")?;
+                Ok(expr)
+            }
+            Token::Op("<<") => Ok(Expr::Tuple(self.list(">>")?)),
+            Token::Op("{") => self.set(),
+            Token::Op("[") => self.bracket(),
+            Token::Op("@") if self.except_values > 0 => {
+                Ok(Expr::Ident(EXCEPT_OLD_VALUE.to_string()))
+            }
+            Token::Op("@") => Err("@ can only be used in the new values of an EXCEPT".to_string()),
+            Token::Op(quantifier @ ("\\A" | "\\E")) => {
+                let bounds = self.bounds()?;
+                self.expect_op(":")?;
+                Ok(Expr::Quantifier(
+                    quantifier == "\\A",
+                    bounds,
+                    Box::new(self.expr(0)?),
+                ))
+            }
+            Token::Ident(name) => match name.as_str() {
+                "TRUE" => Ok(Expr::Bool(true)),
+                "FALSE" => Ok(Expr::Bool(false)),
+                "DOMAIN" => Ok(Expr::Unary("DOMAIN", Box::new(self.expr(10)?))),
+                "UNION" => Ok(Expr::Unary("UNION", Box::new(self.expr(10)?))),
+                "IF" => {
+                    let condition = self.expr(0)?;
+                    self.expect_keyword("THEN")?;
+                    let then = self.expr(0)?;
+                    self.expect_keyword("ELSE")?;
+                    let otherwise = self.expr(0)?;
+                    Ok(Expr::If(
+                        Box::new(condition),
+                        Box::new(then),
+                        Box::new(otherwise),
+                    ))
+                }
+                "CHOOSE" => {
+                    let name = self.ident()?;
+                    self.expect_op("\\in")?;
+                    let set = self.expr(infix_power("\\in").unwrap().1)?;
+                    self.expect_op(":")?;
+                    Ok(Expr::Choose(name, Box::new(set), Box::new(self.expr(0)?)))
+                }
+                _ if self.is_op("(") => {
+                    self.next()?;
+                    Ok(Expr::Call(name, self.list(")")?))
+                }
+                _ => Ok(Expr::Ident(name)),
+            },
+            other => Err(format!("Unexpected {}", other)),
+        }
+    }
+
+    /// A set, after the opening brace
+    fn set(&mut self) -> Result<Expr, String> {
+        if self.is_op("}") {
+            self.next()?;
+            return Ok(Expr::SetEnum(Vec::new()));
+        }
+        let first = self.expr(0)?;
+        if !self.is_op(":") {
+            let mut elements = vec![first];
+            if self.is_op(",") {
+                self.next()?;
+                elements.extend(self.list("}")?);
+            } else {
+                self.expect_op("}")?;
+            }
+            return Ok(Expr::SetEnum(elements));
+        }
+        self.next()?;
+        let set = match first {
+            Expr::Binary("\\in", element, set) => match *element {
+                Expr::Ident(name) => {
+                    let condition = self.expr(0)?;
+                    Expr::SetFilter(name, set, Box::new(condition))
+                }
+                element => Expr::SetMap(
+                    Box::new(Expr::Binary("\\in", Box::new(element), set)),
+                    self.bounds()?,
+                ),
+            },
+            element => Expr::SetMap(Box::new(element), self.bounds()?),
+        };
+        self.expect_op("}")?;
+        Ok(set)
+    }
+
+    /// The keys of an `EXCEPT` update, e.g., `[a][b].c`, after the `!`
+    fn except_path(&mut self) -> Result<Vec<ExceptKey>, String> {
+        let mut path = Vec::new();
+        loop {
+            path.push(match self.next()? {
+                Token::Op("[") => ExceptKey::Index(self.list("]")?),
+                Token::Op(".") => ExceptKey::Field(self.ident()?),
+                other => return Err(format!("Expected [ or ., found {}", other)),
+            });
+            if !(self.is_op("[") || self.is_op(".")) {
+                return Ok(path);
+            }
+        }
+    }
+
+    /// A record, a function, a set of records or functions, or an `EXCEPT`, after the
+    /// opening bracket
+    fn bracket(&mut self) -> Result<Expr, String> {
+        let expr = match (self.peek(), self.peek_at(1)) {
+            (Some(Token::Ident(_)), Some(Token::Op("|->"))) => {
+                let mut fields = Vec::new();
+                loop {
+                    let name = self.ident()?;
+                    self.expect_op("|->")?;
+                    fields.push((name, self.expr(0)?));
+                    if !self.is_op(",") {
+                        break;
+                    }
+                    self.next()?;
+                }
+                Expr::Record(fields)
+            }
+            (Some(Token::Ident(_)), Some(Token::Op("\\in" | ","))) => {
+                let bounds = self.bounds()?;
+                self.expect_op("|->")?;
+                Expr::Function(bounds, Box::new(self.expr(0)?))
+            }
+            (Some(Token::Ident(_)), Some(Token::Op(":"))) => {
+                let mut fields = Vec::new();
+                loop {
+                    let name = self.ident()?;
+                    self.expect_op(":")?;
+                    fields.push((name, self.expr(0)?));
+                    if !self.is_op(",") {
+                        break;
+                    }
+                    self.next()?;
+                }
+                Expr::RecordSet(fields)
+            }
+            _ => {
+                let function = self.expr(0)?;
+                if self.is_op("->") {
+                    self.next()?;
+                    let codomain = self.expr(0)?;
+                    self.expect_op("]")?;
+                    return Ok(Expr::FunctionSet(Box::new(function), Box::new(codomain)));
+                }
+                match self.next()? {
+                    Token::Ident(keyword) if keyword == "EXCEPT" => (),
+                    other => return Err(format!("Expected -> or EXCEPT, found {}", other)),
+                }
+                let mut updates = Vec::new();
+                loop {
+                    self.expect_op("!")?;
+                    let path = self.except_path()?;
+                    self.expect_op("=")?;
+                    self.except_values += 1;
+                    let value = self.expr(0);
+                    self.except_values -= 1;
+                    updates.push((path, value?));
+                    if !self.is_op(",") {
+                        break;
+                    }
+                    self.next()?;
+                }
+                Expr::Except(Box::new(function), updates)
+            }
+        };
+        self.expect_op("]")?;
+        Ok(expr)
+    }
+}
+
+/// A value during evaluation. Records and sequences are functions, as in TLA+, so that
+/// they compare equal to the corresponding functions. Unlike `TlaValue`, integers can be
+/// negative.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Bool(bool),
+    Int(i128),
+    Str(String),
+    /// A model value
+    Model(String),
+    Set(BTreeSet<Value>),
+    Function(BTreeMap<Value, Value>),
+    Variant(String, Box<Value>),
+}
+
+impl Value {
+    fn from_tla(value: &TlaValue) -> Result<Value, String> {
+        Ok(match value {
+            TlaValue::Set(elements) => Value::Set(
+                elements
+                    .iter()
+                    .map(Value::from_tla)
+                    .collect::<Result<_, _>>()?,
+            ),
+            TlaValue::Record(fields) => Value::Function(
+                fields
+                    .iter()
+                    .map(|(k, v)| Ok((Value::Str(k.clone()), Value::from_tla(v)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            TlaValue::Function(map) => Value::Function(
+                map.iter()
+                    .map(|(k, v)| Ok((Value::from_tla(k)?, Value::from_tla(v)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            TlaValue::Seq(elements) => Value::sequence(
+                elements
+                    .iter()
+                    .map(Value::from_tla)
+                    .collect::<Result<_, _>>()?,
+            ),
+            TlaValue::Literal(s) => Value::Str(s.clone()),
+            TlaValue::Constant(s) => Value::Model(s.clone()),
+            TlaValue::Bool(b) => Value::Bool(*b),
+            TlaValue::Int(i) => Value::Int(
+                i.0.to_string()
+                    .parse()
+                    .map_err(|_| format!("Integer {} is too large", i.0))?,
+            ),
+            TlaValue::Variant { tag, value } => {
+                Value::Variant(tag.clone(), Box::new(Value::from_tla(value)?))
+            }
+        })
+    }
+
+    /// Functions with the domain `1..n` become sequences, and the other functions
+    /// become TLA+ functions
+    fn to_tla(&self) -> Result<TlaValue, String> {
+        Ok(match self {
+            Value::Bool(b) => TlaValue::Bool(*b),
+            Value::Int(i) => {
+                TlaValue::Int(Nat::from(u128::try_from(*i).map_err(|_| {
+                    format!("Negative integer {} has no recorded form", i)
+                })?))
+            }
+            Value::Str(s) => TlaValue::Literal(s.clone()),
+            Value::Model(s) => TlaValue::Constant(s.clone()),
+            Value::Set(elements) => TlaValue::Set(
+                elements
+                    .iter()
+                    .map(Value::to_tla)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Function(map) if is_sequence(map) => {
+                TlaValue::Seq(map.values().map(Value::to_tla).collect::<Result<_, _>>()?)
+            }
+            Value::Function(map) => TlaValue::Function(
+                map.iter()
+                    .map(|(k, v)| Ok((k.to_tla()?, v.to_tla()?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            Value::Variant(tag, value) => TlaValue::Variant {
+                tag: tag.clone(),
+                value: Box::new(value.to_tla()?),
+            },
+        })
+    }
+
+    fn sequence(elements: Vec<Value>) -> Value {
+        Value::Function(
+            (1..)
+                .map(Value::Int)
+                .zip(elements)
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    fn as_bool(&self) -> Result<bool, String> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            other => Err(format!("Expected a Boolean, got {}", other)),
+        }
+    }
+
+    fn as_int(&self) -> Result<i128, String> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            other => Err(format!("Expected an integer, got {}", other)),
+        }
+    }
+
+    fn as_set(&self) -> Result<&BTreeSet<Value>, String> {
+        match self {
+            Value::Set(elements) => Ok(elements),
+            other => Err(format!("Expected a set, got {}", other)),
+        }
+    }
+
+    fn as_function(&self) -> Result<&BTreeMap<Value, Value>, String> {
+        match self {
+            Value::Function(map) => Ok(map),
+            other => Err(format!("Expected a function, got {}", other)),
+        }
+    }
+
+    fn as_sequence(&self) -> Result<Vec<&Value>, String> {
+        let map = self.as_function()?;
+        if is_sequence(map) {
+            Ok(map.values().collect())
+        } else {
+            Err(format!("Expected a sequence, got {}", self))
+        }
+    }
+}
+
+/// Whether the domain of the function is `1..n`
+fn is_sequence(map: &BTreeMap<Value, Value>) -> bool {
+    map.keys().zip(1..).all(|(k, i)| *k == Value::Int(i))
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.to_tla() {
+            Ok(value) => write!(f, "{}", value),
+            // Only negative integers lack a recorded form
+            Err(_) => match self {
+                Value::Int(i) => write!(f, "{}", i),
+                other => write!(f, "{:?}", other),
+            },
+        }
+    }
+}
+
+fn checked(result: Option<i128>, op: &str) -> Result<Value, String> {
+    result
+        .map(Value::Int)
+        .ok_or(format!("Integer overflow in {}", op))
+}
+
+fn range(low: i128, high: i128) -> Result<Value, String> {
+    if high.saturating_sub(low) >= MAX_SET_SIZE {
+        return Err(format!(
+            "The set {}..{} is too large to enumerate",
+            low, high
+        ));
+    }
+    Ok(Value::Set((low..=high).map(Value::Int).collect()))
+}
+
+struct Env<'a> {
+    state: &'a GlobalState,
+    constants: &'a TlaConstantAssignment,
+    /// The variables bound by quantifiers and the like, innermost last
+    bound: Vec<(String, Value)>,
+}
+
+impl Env<'_> {
+    fn lookup(&self, name: &str) -> Option<Result<Value, String>> {
+        if let Some((_, value)) = self.bound.iter().rev().find(|(n, _)| n == name) {
+            return Some(Ok(value.clone()));
+        }
+        if let Some(value) = self.state.get(name) {
+            return Some(Value::from_tla(value));
+        }
+        if let Some(value) = self.constants.constants.get(name) {
+            return Some(Value::from_tla(value));
+        }
+        match name {
+            "BOOLEAN" => Some(Ok(Value::Set(BTreeSet::from([
+                Value::Bool(false),
+                Value::Bool(true),
+            ])))),
+            _ => None,
+        }
+    }
+
+    /// Evaluates the body with each element of the set bound to the name, stopping
+    /// when `visit` returns false
+    fn for_each(
+        &mut self,
+        name: &str,
+        set: &Value,
+        mut visit: impl FnMut(&mut Self, &Value) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        for element in set.as_set()? {
+            self.bound.push((name.to_string(), element.clone()));
+            let result = visit(self, element);
+            self.bound.pop();
+            if !result? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// All combinations of values of the bounds
+    fn assignments(&mut self, bounds: &[(String, Expr)]) -> Result<Vec<Vec<Value>>, String> {
+        let Some(((name, set), rest)) = bounds.split_first() else {
+            return Ok(vec![Vec::new()]);
+        };
+        let set = self.eval(set)?;
+        let mut assignments = Vec::new();
+        self.for_each(name, &set, |env, element| {
+            for mut assignment in env.assignments(rest)? {
+                assignment.insert(0, element.clone());
+                assignments.push(assignment);
+            }
+            Ok(true)
+        })?;
+        Ok(assignments)
+    }
+
+    fn with_bindings<T>(
+        &mut self,
+        bounds: &[(String, Expr)],
+        values: &[Value],
+        body: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        for ((name, _), value) in bounds.iter().zip(values) {
+            self.bound.push((name.clone(), value.clone()));
+        }
+        let result = body(self);
+        self.bound.truncate(self.bound.len() - values.len());
+        result
+    }
+
+    /// The function with the value at the path of keys replaced by the new value, in which
+    /// `@` stands for the old one
+    fn except(&mut self, function: &Value, keys: &[Value], value: &Expr) -> Result<Value, String> {
+        let Some((key, rest)) = keys.split_first() else {
+            self.bound
+                .push((EXCEPT_OLD_VALUE.to_string(), function.clone()));
+            let result = self.eval(value);
+            self.bound.pop();
+            return result;
+        };
+        let mut map = function.as_function()?.clone();
+        let old = map
+            .get(key)
+            .ok_or_else(|| format!("{} is not in the domain of {}", key, function))?;
+        let new = self.except(old, rest, value)?;
+        map.insert(key.clone(), new);
+        Ok(Value::Function(map))
+    }
+
+    fn member(&mut self, element: &Value, set: &Expr) -> Result<bool, String> {
+        match set {
+            // Don't enumerate sets of functions and records, which are usually infinite
+            Expr::FunctionSet(domain, codomain) => {
+                let Ok(map) = element.as_function() else {
+                    return Ok(false);
+                };
+                if !map.keys().eq(self.eval(domain)?.as_set()?) {
+                    return Ok(false);
+                }
+                for value in map.values() {
+                    if !self.member(value, codomain)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Expr::RecordSet(fields) => {
+                let Ok(map) = element.as_function() else {
+                    return Ok(false);
+                };
+                if map.len() != fields.len() {
+                    return Ok(false);
+                }
+                for (name, set) in fields {
+                    match map.get(&Value::Str(name.clone())) {
+                        Some(value) if self.member(value, set)? => (),
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            // Don't enumerate ranges and infinite sets
+            Expr::Binary("..", low, high) => {
+                let (low, high) = (self.eval(low)?.as_int()?, self.eval(high)?.as_int()?);
+                Ok(matches!(element, Value::Int(i) if low <= *i && *i <= high))
+            }
+            Expr::Ident(name)
+                if (name == "Nat" || name == "Int") && self.lookup(name).is_none() =>
+            {
+                Ok(matches!(element, Value::Int(i) if name == "Int" || *i >= 0))
+            }
+            _ => Ok(self.eval(set)?.as_set()?.contains(element)),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(i) => Ok(Value::Int(*i)),
+            Expr::Str(s) => Ok(Value::Str(s.clone())),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Ident(name) => self.lookup(name).unwrap_or_else(|| {
+                Err(if name == "Nat" || name == "Int" {
+                    format!("{} is infinite, so it can only be used with \\in", name)
+                } else {
+                    format!("Unknown identifier {}", name)
+                })
+            }),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match *op {
+                    "~" => Ok(Value::Bool(!value.as_bool()?)),
+                    "-" => checked(value.as_int()?.checked_neg(), "-"),
+                    "DOMAIN" => Ok(Value::Set(value.as_function()?.keys().cloned().collect())),
+                    _ => {
+                        let mut union = BTreeSet::new();
+                        for set in value.as_set()? {
+                            union.extend(set.as_set()?.iter().cloned());
+                        }
+                        Ok(Value::Set(union))
+                    }
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => self.binary(op, lhs, rhs),
+            Expr::Apply(function, arguments) => {
+                let function = self.eval(function)?;
+                let argument = match arguments.as_slice() {
+                    [argument] => self.eval(argument)?,
+                    arguments => Value::sequence(
+                        arguments
+                            .iter()
+                            .map(|a| self.eval(a))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                };
+                function
+                    .as_function()?
+                    .get(&argument)
+                    .cloned()
+                    .ok_or(format!("{} is not in the domain of {}", argument, function))
+            }
+            Expr::Field(record, field) => {
+                let record = self.eval(record)?;
+                record
+                    .as_function()?
+                    .get(&Value::Str(field.clone()))
+                    .cloned()
+                    .ok_or(format!("{} has no field {}", record, field))
+            }
+            Expr::Call(name, arguments) => {
+                let arguments: Vec<Value> = arguments
+                    .iter()
+                    .map(|a| self.eval(a))
+                    .collect::<Result<_, _>>()?;
+                call(name, &arguments)
+            }
+            Expr::SetEnum(elements) => Ok(Value::Set(
+                elements
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::SetFilter(name, set, condition) => {
+                let set = self.eval(set)?;
+                let mut filtered = BTreeSet::new();
+                self.for_each(name, &set, |env, element| {
+                    if env.eval(condition)?.as_bool()? {
+                        filtered.insert(element.clone());
+                    }
+                    Ok(true)
+                })?;
+                Ok(Value::Set(filtered))
+            }
+            Expr::SetMap(element, bounds) => {
+                let mut mapped = BTreeSet::new();
+                for values in self.assignments(bounds)? {
+                    mapped.insert(self.with_bindings(bounds, &values, |env| env.eval(element))?);
+                }
+                Ok(Value::Set(mapped))
+            }
+            Expr::Tuple(elements) => Ok(Value::sequence(
+                elements
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Record(fields) => Ok(Value::Function(
+                fields
+                    .iter()
+                    .map(|(name, e)| Ok((Value::Str(name.clone()), self.eval(e)?)))
+                    .collect::<Result<_, String>>()?,
+            )),
+            Expr::Function(bounds, body) => {
+                let mut map = BTreeMap::new();
+                for values in self.assignments(bounds)? {
+                    let value = self.with_bindings(bounds, &values, |env| env.eval(body))?;
+                    let key = match values.as_slice() {
+                        [key] => key.clone(),
+                        keys => Value::sequence(keys.to_vec()),
+                    };
+                    map.insert(key, value);
+                }
+                Ok(Value::Function(map))
+            }
+            Expr::Except(function, updates) => {
+                let mut function = self.eval(function)?;
+                for (path, value) in updates {
+                    let keys = path
+                        .iter()
+                        .map(|key| match key {
+                            ExceptKey::Field(name) => Ok(Value::Str(name.clone())),
+                            ExceptKey::Index(arguments) => match arguments.as_slice() {
+                                [argument] => self.eval(argument),
+                                arguments => Ok(Value::sequence(
+                                    arguments
+                                        .iter()
+                                        .map(|a| self.eval(a))
+                                        .collect::<Result<_, _>>()?,
+                                )),
+                            },
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    function = self.except(&function, &keys, value)?;
+                }
+                Ok(function)
+            }
+            Expr::FunctionSet(..) | Expr::RecordSet(_) => {
+                Err("Sets of functions and records can only be used with \\in".to_string())
+            }
+            Expr::If(condition, then, otherwise) => {
+                if self.eval(condition)?.as_bool()? {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+            Expr::Quantifier(forall, bounds, body) => {
+                for values in self.assignments(bounds)? {
+                    let holds = self
+                        .with_bindings(bounds, &values, |env| env.eval(body))?
+                        .as_bool()?;
+                    if holds != *forall {
+                        return Ok(Value::Bool(holds));
+                    }
+                }
+                Ok(Value::Bool(*forall))
+            }
+            Expr::Choose(name, set, condition) => {
+                let set = self.eval(set)?;
+                let mut chosen = None;
+                self.for_each(name, &set, |env, element| {
+                    if env.eval(condition)?.as_bool()? {
+                        chosen = Some(element.clone());
+                    }
+                    Ok(chosen.is_none())
+                })?;
+                chosen.ok_or(format!("No element of {} satisfies the CHOOSE", set))
+            }
+        }
+    }
+
+    fn binary(&mut self, op: &str, lhs: &Expr, rhs: &Expr) -> Result<Value, String> {
+        // The Boolean connectives only evaluate the right-hand side if needed
+        match op {
+            "/\\" => {
+                return Ok(Value::Bool(
+                    self.eval(lhs)?.as_bool()? && self.eval(rhs)?.as_bool()?,
+                ))
+            }
+            "\\/" => {
+                return Ok(Value::Bool(
+                    self.eval(lhs)?.as_bool()? || self.eval(rhs)?.as_bool()?,
+                ))
+            }
+            "=>" => {
+                return Ok(Value::Bool(
+                    !self.eval(lhs)?.as_bool()? || self.eval(rhs)?.as_bool()?,
+                ))
+            }
+            "\\in" | "\\notin" => {
+                let element = self.eval(lhs)?;
+                return Ok(Value::Bool(self.member(&element, rhs)? == (op == "\\in")));
+            }
+            _ => (),
+        }
+        let (lhs, rhs) = (self.eval(lhs)?, self.eval(rhs)?);
+        match op {
+            "<=>" => Ok(Value::Bool(lhs.as_bool()? == rhs.as_bool()?)),
+            "=" => Ok(Value::Bool(lhs == rhs)),
+            "#" | "/=" => Ok(Value::Bool(lhs != rhs)),
+            "<" => Ok(Value::Bool(lhs.as_int()? < rhs.as_int()?)),
+            ">" => Ok(Value::Bool(lhs.as_int()? > rhs.as_int()?)),
+            "<=" | "=<" => Ok(Value::Bool(lhs.as_int()? <= rhs.as_int()?)),
+            ">=" => Ok(Value::Bool(lhs.as_int()? >= rhs.as_int()?)),
+            "+" => checked(lhs.as_int()?.checked_add(rhs.as_int()?), op),
+            "-" => checked(lhs.as_int()?.checked_sub(rhs.as_int()?), op),
+            "*" => checked(lhs.as_int()?.checked_mul(rhs.as_int()?), op),
+            "\\div" | "%" => {
+                let (lhs, rhs) = (lhs.as_int()?, rhs.as_int()?);
+                if rhs <= 0 {
+                    return Err(format!(
+                        "{} {} {} requires a positive divisor",
+                        lhs, op, rhs
+                    ));
+                }
+                Ok(Value::Int(if op == "%" {
+                    lhs.rem_euclid(rhs)
+                } else {
+                    lhs.div_euclid(rhs)
+                }))
+            }
+            ".." => range(lhs.as_int()?, rhs.as_int()?),
+            "\\subseteq" => Ok(Value::Bool(lhs.as_set()?.is_subset(rhs.as_set()?))),
+            "\\union" => Ok(Value::Set(
+                lhs.as_set()?.union(rhs.as_set()?).cloned().collect(),
+            )),
+            "\\intersect" => Ok(Value::Set(
+                lhs.as_set()?.intersection(rhs.as_set()?).cloned().collect(),
+            )),
+            "\\" => Ok(Value::Set(
+                lhs.as_set()?.difference(rhs.as_set()?).cloned().collect(),
+            )),
+            "\\o" => {
+                let mut elements: Vec<Value> = lhs.as_sequence()?.into_iter().cloned().collect();
+                elements.extend(rhs.as_sequence()?.into_iter().cloned());
+                Ok(Value::sequence(elements))
+            }
+            ":>" => Ok(Value::Function(BTreeMap::from([(lhs, rhs)]))),
+            "@@" => {
+                let mut map = rhs.as_function()?.clone();
+                map.extend(lhs.as_function()?.clone());
+                Ok(Value::Function(map))
+            }
+            _ => Err(format!("Unsupported operator {}", op)),
+        }
+    }
+}
+
+/// The operators of the standard modules that are supported
+fn call(name: &str, arguments: &[Value]) -> Result<Value, String> {
+    match (name, arguments) {
+        ("Cardinality", [set]) => Ok(Value::Int(set.as_set()?.len() as i128)),
+        ("Len", [sequence]) => Ok(Value::Int(sequence.as_sequence()?.len() as i128)),
+        ("Head", [sequence]) => sequence
+            .as_sequence()?
+            .first()
+            .map(|v| (*v).clone())
+            .ok_or("Head of the empty sequence".to_string()),
+        ("Tail", [sequence]) => match sequence.as_sequence()?.split_first() {
+            Some((_, tail)) => Ok(Value::sequence(tail.iter().map(|v| (*v).clone()).collect())),
+            None => Err("Tail of the empty sequence".to_string()),
+        },
+        ("Append", [sequence, element]) => {
+            let mut elements: Vec<Value> = sequence.as_sequence()?.into_iter().cloned().collect();
+            elements.push(element.clone());
+            Ok(Value::sequence(elements))
+        }
+        _ => Err(format!(
+            "Unsupported operator {} with {} arguments",
+            name,
+            arguments.len()
+        )),
+    }
+}
+
+/// A TLA+ expression that is evaluated directly on recorded states, without a model
+/// checker. It supports arithmetic, comparisons, Boolean connectives, set, function,
+/// record and sequence operations (including `EXCEPT` with nested paths and `@`),
+/// quantifiers and `CHOOSE` over finite sets, `DOMAIN`, and `IF`. Like `Nat` and `Int`,
+/// the sets of functions `[S -> T]` and of records `[a : S]` can only be used with
+/// `\in`. The names in the expression are bound variables, the variables of the state
+/// or the constants. As in TLA+, the items of bulleted conjunction and disjunction lists
+/// are delimited by the columns of their bullets.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    text: String,
+    expr: Expr,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(text).map_err(|e| format!("Can't parse {}: {}", text, e))?;
+        let expr = parser
+            .expr(0)
+            .and_then(|expr| match parser.peek() {
+                None => Ok(expr),
+                Some(token) => Err(format!("Unexpected {}", token)),
+            })
+            .map_err(|e| format!("Can't parse {}: {}", text, e))?;
+        Ok(Self {
+            text: text.to_string(),
+            expr,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The value of the expression. Functions with the domain `1..n` are returned as
+    /// sequences.
+    pub fn evaluate(
+        &self,
+        state: &GlobalState,
+        constants: &TlaConstantAssignment,
+    ) -> Result<TlaValue, String> {
+        self.value(state, constants)?.to_tla()
+    }
+
+    /// Whether the expression, which must be a Boolean, holds in the state
+    pub fn holds(
+        &self,
+        state: &GlobalState,
+        constants: &TlaConstantAssignment,
+    ) -> Result<bool, String> {
+        self.value(state, constants)?.as_bool()
+    }
+
+    fn value(
+        &self,
+        state: &GlobalState,
+        constants: &TlaConstantAssignment,
+    ) -> Result<Value, String> {
+        Env {
+            state,
+            constants,
+            bound: Vec::new(),
+        }
+        .eval(&self.expr)
+        .map_err(|e| format!("Can't evaluate {}: {}", self.text, e))
+    }
+
+    /// Checks the expression as an invariant of the pre- and post-states of all pairs of
+    /// the trace, and returns the first state where it doesn't hold
+    pub fn check_trace(&self, trace: &UpdateTrace) -> Result<(), InvariantViolation> {
+        for (pair_index, pair) in trace.state_pairs.iter().enumerate() {
+            for (post_state, state) in [(false, &pair.start), (true, &pair.end)] {
+                let reason = match self.holds(state, &trace.constants) {
+                    Ok(true) => continue,
+                    Ok(false) => "the invariant is FALSE".to_string(),
+                    Err(e) => e,
+                };
+                return Err(InvariantViolation {
+                    invariant: self.text.clone(),
+                    pair_index,
+                    post_state,
+                    state: state.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses and evaluates the expression in the state
+pub fn evaluate(
+    expression: &str,
+    state: &GlobalState,
+    constants: &TlaConstantAssignment,
+) -> Result<TlaValue, String> {
+    Expression::parse(expression)?.evaluate(state, constants)
+}
+
+/// A recorded state in which an invariant doesn't hold
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub invariant: String,
+    pub pair_index: usize,
+    /// Whether the state is the post-state of the pair, rather than the pre-state
+    pub post_state: bool,
+    pub state: GlobalState,
+    /// Either that the invariant is false, or why it couldn't be evaluated
+    pub reason: String,
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Invariant {} is violated in the {} of state pair {}: {}",
+            self.invariant,
+            if self.post_state {
+                "post-state"
+            } else {
+                "pre-state"
+            },
+            self.pair_index,
+            self.reason
+        )?;
+        write!(f, "{:?}", self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{no_constants, update};
+    use crate::{ResolvedStatePair, ToTla};
+
+    fn state() -> (GlobalState, TlaConstantAssignment) {
+        let mut state = GlobalState::new();
+        state.add("counter", 3_u64.to_tla_value());
+        state.add(
+            "balances",
+            BTreeMap::from([("alice".to_string(), 10_u64), ("bob".to_string(), 0_u64)])
+                .to_tla_value(),
+        );
+        state.add(
+            "pc",
+            BTreeMap::from([("p1".to_string(), "Start".to_string())]).to_tla_value(),
+        );
+        state.add(
+            "requests",
+            TlaValue::Seq(vec![TlaValue::Record(BTreeMap::from([
+                ("caller".to_string(), "p1".to_tla_value()),
+                ("amount".to_string(), 5_u64.to_tla_value()),
+            ]))]),
+        );
+        let constants = TlaConstantAssignment {
+            constants: BTreeMap::from([
+                ("MAX_COUNTER".to_string(), 5_u64.to_tla_value()),
+                (
+                    "USERS".to_string(),
+                    BTreeSet::from(["alice".to_string(), "bob".to_string()]).to_tla_value(),
+                ),
+            ]),
+        };
+        (state, constants)
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let (state, constants) = state();
+        let holds = |text: &str| {
+            Expression::parse(text)
+                .unwrap()
+                .holds(&state, &constants)
+                .unwrap()
+        };
+        let value = |text: &str| evaluate(text, &state, &constants).unwrap().to_string();
+
+        assert!(holds("counter <= MAX_COUNTER"));
+        assert!(!holds("counter > MAX_COUNTER \\/ counter = 4"));
+        assert!(holds("counter - MAX_COUNTER = -2 /\\ 7 \\div 2 = 3"));
+        assert!(holds("~ counter = 2 => counter \\in 1..MAX_COUNTER"));
+        assert!(holds("\\A u \\in USERS : balances[u] >= 0"));
+        assert!(holds("\\E u \\in DOMAIN balances : balances[u] = 10"));
+        assert!(holds("\\A x, y \\in 1..3 : x + y <= 6"));
+        assert!(holds("DOMAIN balances = USERS /\\ pc[\"p1\"] = \"Start\""));
+        assert!(holds("Len(requests) = 1 /\\ Head(requests).amount = 5"));
+        assert!(holds("requests[1] = [amount |-> 5, caller |-> \"p1\"]"));
+        assert!(holds(
+            "{u \\in USERS : balances[u] > 0} \\subseteq {\"alice\"}"
+        ));
+        assert!(holds("counter \\in Nat /\\ -1 \\notin Nat"));
+        assert!(holds("<<1, 2>> = [i \\in 1..2 |-> i]"));
+        assert!(holds("IF counter > 2 THEN TRUE ELSE 1 \\div 0 = 0"));
+        assert_eq!(value("{balances[u] * 2 : u \\in USERS}"), "{0, 20}");
+        assert_eq!(
+            value("USERS \\ {\"bob\"} \\union {\"carol\"}"),
+            "{\"alice\", \"carol\"}"
+        );
+        assert_eq!(
+            value("[balances EXCEPT ![\"bob\"] = 1]"),
+            "(\"alice\" :> 10 @@ \"bob\" :> 1)"
+        );
+        assert_eq!(
+            value("[balances EXCEPT ![\"alice\"] = @ - 4, ![\"bob\"] = @ + 1]"),
+            "(\"alice\" :> 6 @@ \"bob\" :> 1)"
+        );
+        assert!(holds(
+            "[requests EXCEPT ![1].amount = @ * 2][1] = [caller |-> \"p1\", amount |-> 10]"
+        ));
+        assert!(holds(
+            "[[a |-> [b |-> 1]] EXCEPT !.a.b = @ + 1, ![\"a\"] = [@ EXCEPT !.b = @ * 3]] = [a |-> [b |-> 6]]"
+        ));
+        assert!(holds(
+            "balances \\in [USERS -> Nat] /\\ balances \\notin [USERS -> 1..10]"
+        ));
+        assert!(holds(
+            "requests \\in [1..1 -> [caller : DOMAIN pc, amount : Nat]]"
+        ));
+        assert!(!holds("requests[1] \\in [caller : DOMAIN pc]"));
+        assert_eq!(value("Append(<<1>>, 2) \\o Tail(<<3, 4>>)"), "<<1, 2, 4>>");
+        assert_eq!(value("CHOOSE x \\in 1..5 : x * x > 5"), "3");
+        assert_eq!(value("Cardinality(UNION {{1, 2}, {2, 3}})"), "3");
+    }
+
+    #[test]
+    fn respects_precedence() {
+        let (state, constants) = state();
+        let value = |text: &str| evaluate(text, &state, &constants).unwrap().to_string();
+
+        // Prefix minus and * bind tighter than %
+        assert_eq!(value("-7 % 3"), "2");
+        assert_eq!(value("(-7) % 3"), "2");
+        assert_eq!(value("-(7 % 3) = -1"), "TRUE");
+        assert_eq!(value("2 * 5 % 3"), "1");
+        assert_eq!(value("1 + 2 * 3"), "7");
+        assert_eq!(value("10 - 2 - 3"), "5");
+        assert_eq!(value("1..2 \\union {5}"), "{1, 2, 5}");
+        assert_eq!(value("FALSE => FALSE <=> FALSE"), "TRUE");
+        assert_eq!(value("(FALSE => FALSE) => FALSE"), "FALSE");
+        assert_eq!(value("~ counter = 2 /\\ counter = 3"), "TRUE");
+    }
+
+    #[test]
+    fn reads_junction_lists() {
+        let holds = |text: &str, x: u64| {
+            let mut state = GlobalState::new();
+            state.add("x", x.to_tla_value());
+            state.add("y", 2_u64.to_tla_value());
+            state.add("z", 3_u64.to_tla_value());
+            Expression::parse(text)
+                .unwrap()
+                .holds(&state, &no_constants())
+                .unwrap()
+        };
+        let nested = "\
+/\\ x = 1
+/\\ \\/ y = 1
+   \\/ y = 2
+/\\ z = 3";
+        let outer_disjunction = "\
+\\/ /\\ x = 1
+   /\\ y = 1
+\\/ z = 3";
+
+        assert!(!holds(nested, 0));
+        assert!(holds(nested, 1));
+        assert!(holds(outer_disjunction, 0));
+        // On a single line, every bullet after the first one is an infix operator
+        assert!(!holds("/\\ x = 1 /\\ \\/ y = 1 \\/ y = 2 /\\ z = 3", 0));
+        assert!(holds("(\\/ x = 1\n \\/ y = 2) /\\ z = 3", 0));
+    }
+
+    #[test]
+    fn reports_evaluation_errors() {
+        let (state, constants) = state();
+        let error = |text: &str| evaluate(text, &state, &constants).unwrap_err();
+
+        assert!(error("\\A x \\in Nat : x >= 0").contains("Nat is infinite"));
+        assert!(error("CHOOSE x \\in 1..3 : x > 5").contains("No element of {1, 2, 3}"));
+        assert!(error("counter \\div 0").contains("3 \\div 0 requires a positive divisor"));
+        assert!(error("counter % 0").contains("requires a positive divisor"));
+        assert!(error("missing + 1").contains("Unknown identifier missing"));
+        assert!(error("balances[\"carol\"]").contains("\"carol\" is not in the domain"));
+        assert!(Expression::parse("counter <= ")
+            .unwrap_err()
+            .contains("Unexpected end of expression"));
+        assert!(Expression::parse("counter <= 1)")
+            .unwrap_err()
+            .contains("Unexpected )"));
+        assert!(Expression::parse("FALSE => FALSE => FALSE")
+            .unwrap_err()
+            .contains("=> is not associative"));
+        assert!(Expression::parse("@ + 1")
+            .unwrap_err()
+            .contains("@ can only be used in the new values of an EXCEPT"));
+        assert!(Expression::parse("[balances \\union 1]")
+            .unwrap_err()
+            .contains("Expected -> or EXCEPT, found ]"));
+        assert!(error("[USERS -> Nat] = {}").contains("can only be used with \\in"));
+    }
+
+    #[test]
+    fn checks_invariants_on_traces() {
+        let counter = |value: u64| {
+            let mut state = GlobalState::new();
+            state.add("counter", value.to_tla_value());
+            state
+        };
+        let trace = UpdateTrace {
+            update: update("counter", "p1"),
+            state_pairs: vec![
+                ResolvedStatePair {
+                    start: counter(0),
+                    end: counter(2),
+                },
+                ResolvedStatePair {
+                    start: counter(2),
+                    end: counter(4),
+                },
+            ],
+            constants: TlaConstantAssignment {
+                constants: BTreeMap::from([("MAX".to_string(), 3_u64.to_tla_value())]),
+            },
+        };
+
+        let bounded = Expression::parse("counter <= MAX").unwrap();
+        let even = Expression::parse("counter % 2 = 0").unwrap();
+        let violation = bounded.check_trace(&trace).unwrap_err();
+
+        assert!(even.check_trace(&trace).is_ok());
+        assert_eq!(violation.pair_index, 1);
+        assert!(violation.post_state);
+        assert_eq!(violation.state, counter(4));
+        assert!(violation
+            .to_string()
+            .starts_with("Invariant counter <= MAX is violated in the post-state of state pair 1"));
+    }
+}
@@ -1,12 +1,26 @@
 pub mod checker;
+pub mod coverage;
+pub mod evaluation;
 pub mod tla_state;
 pub mod tla_value;
+pub mod trace_file;
+pub mod trace_normalization;
+pub mod trace_stitching;
+
+#[cfg(test)]
+mod test_helpers;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::mem;
 use std::rc::Rc;
 
+pub use coverage::*;
+pub use evaluation::*;
 pub use tla_state::*;
 pub use tla_value::*;
+pub use trace_file::*;
+pub use trace_normalization::*;
+pub use trace_stitching::*;
 
 #[derive(Clone, Debug)]
 pub struct Update {
@@ -26,6 +40,46 @@ pub struct Update {
     pub canister_name: String,
     /// Cleans up the trace and extracts the constants from it
     pub post_process: fn(&mut Vec<ResolvedStatePair>) -> TlaConstantAssignment,
+    /// How call outcomes are stored in the response buffers
+    pub response_encoding: ResponseEncoding,
+}
+
+impl Update {
+    /// An update without default locals or post-processing, whose responses use the
+    /// plain encoding. Use the `with_` methods to set the other fields.
+    pub fn new(start_label: &str, end_label: &str, process_id: &str, canister_name: &str) -> Self {
+        Self {
+            default_start_locals: VarAssignment::new(),
+            default_end_locals: VarAssignment::new(),
+            start_label: Label::new(start_label),
+            end_label: Label::new(end_label),
+            process_id: process_id.to_string(),
+            canister_name: canister_name.to_string(),
+            post_process: |_| TlaConstantAssignment {
+                constants: BTreeMap::new(),
+            },
+            response_encoding: ResponseEncoding::Plain,
+        }
+    }
+
+    pub fn with_default_locals(mut self, start: VarAssignment, end: VarAssignment) -> Self {
+        self.default_start_locals = start;
+        self.default_end_locals = end;
+        self
+    }
+
+    pub fn with_post_process(
+        mut self,
+        post_process: fn(&mut Vec<ResolvedStatePair>) -> TlaConstantAssignment,
+    ) -> Self {
+        self.post_process = post_process;
+        self
+    }
+
+    pub fn with_response_encoding(mut self, response_encoding: ResponseEncoding) -> Self {
+        self.response_encoding = response_encoding;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -201,13 +255,14 @@ pub fn log_request(
         unresolved,
         state.context.update.process_id.as_str(),
         state.context.update.canister_name.as_str(),
+        &state.context.update.response_encoding,
     )
 }
 
 pub fn log_response(
     state: &mut MessageHandlerState,
     from: Destination,
-    message: TlaValue,
+    outcome: CallOutcome,
     global: GlobalState,
 ) {
     let local = state.context.get_state();
@@ -215,16 +270,53 @@ pub fn log_response(
     assert!(
         matches!(stage, Stage::Start),
         "Receiving response {} from {} in end stage",
-        message,
+        outcome,
         from
     );
     *stage = Stage::End(StartState {
         global: global,
         local,
-        responses: vec![ResponseBuffer { from, message }],
+        responses: vec![ResponseBuffer { from, outcome }],
+    });
+    state.context.global = GlobalState::new();
+    state.context.locals = VarAssignment::new();
+}
+
+/// Ends the current state pair at the given label and starts a new one from there,
+/// without any requests or responses. Used when the model splits a single message
+/// handler into several labels.
+pub fn log_label(
+    state: &mut MessageHandlerState,
+    label: &str,
+    global: GlobalState,
+) -> ResolvedStatePair {
+    state.context.location.0 = vec![LocationStackElem::Label(Label::new(label))];
+    let start_state = match mem::replace(&mut state.stage, Stage::Start) {
+        Stage::End(start) => start,
+        _ => panic!("Reaching label {}, but stage is start", label),
+    };
+    let local = state.context.get_state();
+    let unresolved = StatePair {
+        start: start_state,
+        end: EndState {
+            global: global.clone(),
+            local: local.clone(),
+            requests: Vec::new(),
+        },
+    };
+    state.stage = Stage::End(StartState {
+        global,
+        local,
+        responses: Vec::new(),
     });
     state.context.global = GlobalState::new();
     state.context.locals = VarAssignment::new();
+    ResolvedStatePair::resolve(
+        unresolved,
+        state.context.update.process_id.as_str(),
+        state.context.update.canister_name.as_str(),
+        &state.context.update.response_encoding,
+    )
 }
 
 pub fn log_fn_call(state: &mut MessageHandlerState) {
@@ -262,6 +354,7 @@ pub fn log_method_return(
         unresolved,
         state.context.update.process_id.as_str(),
         state.context.update.canister_name.as_str(),
+        &state.context.update.response_encoding,
     )
 }
 
@@ -354,6 +447,8 @@ macro_rules! tla_log_request {
 }
 
 /// Logs the receipt of a response (that starts a new message handler).
+/// The response can be any value with a TLA representation, a `CallOutcome`, or
+/// the `Result` of the call (see `ToCallOutcome`).
 /// It assumes that there are the following two functions in scope:
 /// TODO: update the comment here after the design is stabilized
 /// 1. `tla_get_globals() -> GlobalState`
@@ -361,7 +456,7 @@ macro_rules! tla_log_request {
 #[macro_export]
 macro_rules! tla_log_response {
     ($from:expr, $message:expr) => {{
-        let message = $message.to_tla_value();
+        let message = $crate::ToCallOutcome::to_call_outcome(&$message);
         let res = TLA_INSTRUMENTATION_STATE.try_with(|state| {
             let mut handler_state = state.handler_state.borrow_mut();
             let globals = (*state.globals_snapshotter)();
@@ -376,6 +471,60 @@ macro_rules! tla_log_response {
     }};
 }
 
+/// Performs an instrumented inter-canister call: logs the request, awaits the call
+/// future, logs its result as the response (see `ToCallOutcome`) and evaluates to
+/// the result. This keeps the request and the response logging in sync. Must be used
+/// in an async context, with the same requirements as `tla_log_request!` and
+/// `tla_log_response!`.
+/// If errors of the call can also be synchronous failures, pass a function from a
+/// reference to the result to its `CallOutcome` as a last `outcome = ...` argument.
+#[macro_export]
+macro_rules! tla_call {
+    ($label:expr, $to:expr, $method:expr, $args:expr, $call:expr) => {
+        $crate::tla_call!(
+            $label,
+            $to,
+            $method,
+            $args,
+            $call,
+            outcome = $crate::ToCallOutcome::to_call_outcome
+        )
+    };
+    ($label:expr, $to:expr, $method:expr, $args:expr, $call:expr, outcome = $outcome:expr) => {{
+        let destination: $crate::Destination = $to;
+        $crate::tla_log_request!($label, destination.clone(), $method, $args);
+        let result = $call.await;
+        let outcome: $crate::CallOutcome = ($outcome)(&result);
+        $crate::tla_log_response!(destination.clone(), outcome);
+        result
+    }};
+}
+
+/// Logs reaching a label in the middle of a message handler. This ends the current
+/// state pair and starts a new one at the label, without any buffer traffic.
+/// It assumes the same task-local `TLA_INSTRUMENTATION_STATE` as `tla_log_request!`.
+#[macro_export]
+macro_rules! tla_log_label {
+    ($label:expr) => {{
+        let res = TLA_INSTRUMENTATION_STATE.try_with(|state| {
+            let mut handler_state = state.handler_state.borrow_mut();
+            let globals = (*state.globals_snapshotter)();
+            let new_state_pair = $crate::log_label(&mut handler_state, $label, globals);
+            let mut state_pairs = state.state_pairs.borrow_mut();
+            state_pairs.push(new_state_pair);
+        });
+        match res {
+            Ok(_) => (),
+            Err(_) => {
+                println!(
+                    "Asked to log label {}, but instrumentation not initialized",
+                    $label
+                );
+            }
+        };
+    }};
+}
+
 /// Logs the start of a method (top-level update)
 /// It assumes that there are the following two functions in scope:
 /// 1. `tla_get_globals() -> GlobalState`
@@ -0,0 +1,35 @@
+//! Fixtures shared by the unit tests
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{ResolvedStatePair, TlaConstantAssignment, Update, UpdateTrace};
+
+/// A fresh directory for the files of the test, removing any left over by earlier runs
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tla_{}_test_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub(crate) fn no_constants() -> TlaConstantAssignment {
+    TlaConstantAssignment {
+        constants: BTreeMap::new(),
+    }
+}
+
+/// An update of the process from `Start` to `End`
+pub(crate) fn update(canister_name: &str, process_id: &str) -> Update {
+    Update::new("Start", "End", process_id, canister_name)
+}
+
+/// A trace of the update that records no constants
+pub(crate) fn trace(update: Update, state_pairs: Vec<ResolvedStatePair>) -> UpdateTrace {
+    UpdateTrace {
+        update,
+        state_pairs,
+        constants: no_constants(),
+    }
+}
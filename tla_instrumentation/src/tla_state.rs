@@ -2,7 +2,7 @@ use crate::tla_value::{TlaValue, ToTla};
 use candid::CandidType;
 use serde::Deserialize;
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt,
     fmt::{Display, Formatter},
 };
@@ -51,7 +51,7 @@ impl VarAssignment {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, CandidType, Deserialize)]
 pub struct GlobalState(pub VarAssignment);
 
 impl GlobalState {
@@ -74,6 +74,28 @@ impl GlobalState {
     pub fn get(&self, name: &str) -> Option<&TlaValue> {
         self.0 .0.get(name)
     }
+
+    /// The variables with their values in TLA+ syntax
+    pub fn to_map(&self) -> HashMap<String, String> {
+        self.0
+             .0
+            .iter()
+            .map(|(k, v)| (k.clone(), v.to_string()))
+            .collect()
+    }
+
+    /// The label of the process in the `pc` variable, if it's recorded there
+    pub fn pc_label(&self, process_id: &str) -> Option<&str> {
+        match self.get("pc") {
+            Some(TlaValue::Function(pc)) => {
+                match pc.get(&TlaValue::Literal(process_id.to_string())) {
+                    Some(TlaValue::Literal(label)) => Some(label),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Debug for GlobalState {
@@ -98,6 +120,10 @@ impl Label {
     pub fn merge(&self, other: &Label) -> Label {
         Label(format!("{}_{}", self.0, other.0))
     }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -128,10 +154,136 @@ pub struct RequestBuffer {
     pub args: TlaValue,
 }
 
+/// The outcome of an inter-canister call, as observed by the caller
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallOutcome {
+    Ok(TlaValue),
+    /// The callee (or the system) rejected the call
+    Reject {
+        code: String,
+        message: String,
+    },
+    /// The call failed before it was sent
+    SyncFailure {
+        message: String,
+    },
+}
+
+impl Display for CallOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CallOutcome::Ok(value) => write!(f, "Ok({})", value),
+            CallOutcome::Reject { code, message } => {
+                write!(f, "Reject({}, \"{}\")", code, message)
+            }
+            CallOutcome::SyncFailure { message } => write!(f, "SyncFailure(\"{}\")", message),
+        }
+    }
+}
+
+/// Converts the result of an inter-canister call into a `CallOutcome`.
+/// Any value with a TLA representation is a successful outcome, and a
+/// `Result<T, (code, message)>` (the shape of the IC's `CallResult`) maps
+/// errors to rejects, with the code rendered through its `Debug` implementation.
+pub trait ToCallOutcome {
+    fn to_call_outcome(&self) -> CallOutcome;
+}
+
+impl<T: ToTla> ToCallOutcome for T {
+    fn to_call_outcome(&self) -> CallOutcome {
+        CallOutcome::Ok(self.to_tla_value())
+    }
+}
+
+impl<T: ToTla, C: fmt::Debug> ToCallOutcome for Result<T, (C, String)> {
+    fn to_call_outcome(&self) -> CallOutcome {
+        match self {
+            Ok(value) => CallOutcome::Ok(value.to_tla_value()),
+            Err((code, message)) => CallOutcome::Reject {
+                code: format!("{:?}", code),
+                message: message.clone(),
+            },
+        }
+    }
+}
+
+impl ToCallOutcome for CallOutcome {
+    fn to_call_outcome(&self) -> CallOutcome {
+        self.clone()
+    }
+}
+
+/// The variant tags used to encode call outcomes in the response buffers
+#[derive(Clone, Debug)]
+pub struct OutcomeTags {
+    pub ok: String,
+    pub reject: String,
+    pub sync_failure: String,
+}
+
+impl Default for OutcomeTags {
+    fn default() -> Self {
+        Self {
+            ok: "Ok".to_string(),
+            reject: "Reject".to_string(),
+            sync_failure: "SyncFailure".to_string(),
+        }
+    }
+}
+
+/// How call outcomes are stored in the `response` field of the response buffers.
+/// Rejects are encoded as `Variant(reject, [code |-> ..., message |-> ...])`, and
+/// synchronous failures as `Variant(sync_failure, [message |-> ...])`.
+#[derive(Clone, Debug, Default)]
+pub enum ResponseEncoding {
+    /// Successful responses are stored as they are, rejects and failures as variants
+    /// with the default tags. Suitable for models where calls always succeed.
+    #[default]
+    Plain,
+    /// All outcomes are stored as variants with the given tags
+    Variant(OutcomeTags),
+}
+
+impl ResponseEncoding {
+    pub fn encode(&self, outcome: &CallOutcome) -> TlaValue {
+        let default_tags = OutcomeTags::default();
+        let tags = match self {
+            ResponseEncoding::Plain => {
+                if let CallOutcome::Ok(value) = outcome {
+                    return value.clone();
+                }
+                &default_tags
+            }
+            ResponseEncoding::Variant(tags) => tags,
+        };
+        let (tag, value) = match outcome {
+            CallOutcome::Ok(value) => (&tags.ok, value.clone()),
+            CallOutcome::Reject { code, message } => (
+                &tags.reject,
+                TlaValue::Record(BTreeMap::from([
+                    ("code".to_string(), code.to_tla_value()),
+                    ("message".to_string(), message.to_tla_value()),
+                ])),
+            ),
+            CallOutcome::SyncFailure { message } => (
+                &tags.sync_failure,
+                TlaValue::Record(BTreeMap::from([(
+                    "message".to_string(),
+                    message.to_tla_value(),
+                )])),
+            ),
+        };
+        TlaValue::Variant {
+            tag: tag.clone(),
+            value: Box::new(value),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ResponseBuffer {
     pub from: Destination,
-    pub message: TlaValue,
+    pub outcome: CallOutcome,
 }
 
 #[derive(Clone, Debug)]
@@ -155,7 +307,7 @@ pub struct StatePair {
 }
 
 /// A pair of states with local variable names resolved to functions from the process ID
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, CandidType, Deserialize)]
 pub struct ResolvedStatePair {
     pub start: GlobalState,
     pub end: GlobalState,
@@ -211,13 +363,17 @@ fn resolve_response_buffers(
     responses: Vec<ResponseBuffer>,
     canister_name: &str,
     process_id: &str,
+    encoding: &ResponseEncoding,
 ) -> VarAssignment {
     let mut resolved_response_buffers = VarAssignment::new();
     for response_buffer in responses {
         let buffer_global = format!("{}_to_{}", response_buffer.from.0, canister_name);
         let buffer_contents = TlaValue::Set(BTreeSet::from([TlaValue::Record(BTreeMap::from([
             ("caller".to_string(), process_id.to_tla_value()),
-            ("response".to_string(), response_buffer.message.clone()),
+            (
+                "response".to_string(),
+                encoding.encode(&response_buffer.outcome),
+            ),
         ]))]));
         resolved_response_buffers.push(&buffer_global, buffer_contents);
     }
@@ -229,6 +385,7 @@ impl ResolvedStatePair {
         unresolved: StatePair,
         process_id: &str,
         canister_name: &str,
+        response_encoding: &ResponseEncoding,
     ) -> ResolvedStatePair {
         let resolved_start_locals = resolve_locals(unresolved.start.local.locals, process_id);
         let start_pc = resolve_local_variable(
@@ -244,8 +401,12 @@ impl ResolvedStatePair {
         );
         // println!("Resolved start locals: {:?}", resolved_start_locals);
         // println!("Resolved end locals: {:?}", resolved_end_locals);
-        let resolved_responses =
-            resolve_response_buffers(unresolved.start.responses, canister_name, process_id);
+        let resolved_responses = resolve_response_buffers(
+            unresolved.start.responses,
+            canister_name,
+            process_id,
+            response_encoding,
+        );
         let resolved_requests =
             resolve_request_buffers(unresolved.end.requests, canister_name, process_id);
         ResolvedStatePair {
@@ -307,4 +468,47 @@ mod tests {
             expected_assignment
         );
     }
+
+    #[test]
+    fn test_response_encoding() {
+        let ok: Result<u64, (u32, String)> = Ok(3);
+        let rejected: Result<u64, (u32, String)> = Err((4, "out of cycles".to_string()));
+        assert_eq!(ok.to_call_outcome(), CallOutcome::Ok(3_u64.to_tla_value()));
+        let reject = rejected.to_call_outcome();
+        assert_eq!(
+            reject,
+            CallOutcome::Reject {
+                code: "4".to_string(),
+                message: "out of cycles".to_string()
+            }
+        );
+
+        assert_eq!(
+            ResponseEncoding::Plain.encode(&ok.to_call_outcome()),
+            3_u64.to_tla_value()
+        );
+        let tags = OutcomeTags {
+            ok: "Success".to_string(),
+            ..OutcomeTags::default()
+        };
+        assert_eq!(
+            ResponseEncoding::Variant(tags.clone()).encode(&ok.to_call_outcome()),
+            TlaValue::Variant {
+                tag: "Success".to_string(),
+                value: Box::new(3_u64.to_tla_value())
+            }
+        );
+        let expected_reject = TlaValue::Variant {
+            tag: "Reject".to_string(),
+            value: Box::new(TlaValue::Record(BTreeMap::from([
+                ("code".to_string(), "4".to_tla_value()),
+                ("message".to_string(), "out of cycles".to_tla_value()),
+            ]))),
+        };
+        assert_eq!(ResponseEncoding::Plain.encode(&reject), expected_reject);
+        assert_eq!(
+            ResponseEncoding::Variant(tags).encode(&reject),
+            expected_reject
+        );
+    }
 }
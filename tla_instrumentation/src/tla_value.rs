@@ -186,3 +186,134 @@ impl ToTla for String {
         TlaValue::Literal(self.clone())
     }
 }
+
+/// A difference between two values at some position inside them. A missing old (new)
+/// value means that the element, field or key was added (removed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueChange {
+    /// How to get to the changed part, e.g., `.balance["alice"][2]`; empty for the value itself
+    pub path: String,
+    pub old: Option<TlaValue>,
+    pub new: Option<TlaValue>,
+}
+
+impl Display for ValueChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<TlaValue>| {
+            v.as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or("(none)".to_string())
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.path,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+fn diff_at(path: String, old: &TlaValue, new: &TlaValue, changes: &mut Vec<ValueChange>) {
+    fn diff_maps<K: Ord>(
+        path: &str,
+        old: &BTreeMap<K, TlaValue>,
+        new: &BTreeMap<K, TlaValue>,
+        key_path: impl Fn(&K) -> String,
+        changes: &mut Vec<ValueChange>,
+    ) {
+        for (k, old_v) in old {
+            match new.get(k) {
+                Some(new_v) => diff_at(format!("{}{}", path, key_path(k)), old_v, new_v, changes),
+                None => changes.push(ValueChange {
+                    path: format!("{}{}", path, key_path(k)),
+                    old: Some(old_v.clone()),
+                    new: None,
+                }),
+            }
+        }
+        for (k, new_v) in new {
+            if !old.contains_key(k) {
+                changes.push(ValueChange {
+                    path: format!("{}{}", path, key_path(k)),
+                    old: None,
+                    new: Some(new_v.clone()),
+                });
+            }
+        }
+    }
+
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (TlaValue::Record(old), TlaValue::Record(new)) => {
+            diff_maps(&path, old, new, |k| format!(".{}", k), changes)
+        }
+        (TlaValue::Function(old), TlaValue::Function(new)) => {
+            diff_maps(&path, old, new, |k| format!("[{}]", k), changes)
+        }
+        (TlaValue::Seq(old), TlaValue::Seq(new)) => {
+            // TLA+ sequences are 1-indexed
+            let index = |i: usize| format!("[{}]", i + 1);
+            for (i, (old_v, new_v)) in old.iter().zip(new.iter()).enumerate() {
+                diff_at(format!("{}{}", path, index(i)), old_v, new_v, changes);
+            }
+            for (i, old_v) in old.iter().enumerate().skip(new.len()) {
+                changes.push(ValueChange {
+                    path: format!("{}{}", path, index(i)),
+                    old: Some(old_v.clone()),
+                    new: None,
+                });
+            }
+            for (i, new_v) in new.iter().enumerate().skip(old.len()) {
+                changes.push(ValueChange {
+                    path: format!("{}{}", path, index(i)),
+                    old: None,
+                    new: Some(new_v.clone()),
+                });
+            }
+        }
+        (TlaValue::Set(old), TlaValue::Set(new)) => {
+            for removed in old.difference(new) {
+                changes.push(ValueChange {
+                    path: path.clone(),
+                    old: Some(removed.clone()),
+                    new: None,
+                });
+            }
+            for added in new.difference(old) {
+                changes.push(ValueChange {
+                    path: path.clone(),
+                    old: None,
+                    new: Some(added.clone()),
+                });
+            }
+        }
+        (
+            TlaValue::Variant {
+                tag: old_tag,
+                value: old_value,
+            },
+            TlaValue::Variant {
+                tag: new_tag,
+                value: new_value,
+            },
+        ) if old_tag == new_tag => diff_at(path, old_value, new_value, changes),
+        _ => changes.push(ValueChange {
+            path,
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+        }),
+    }
+}
+
+impl TlaValue {
+    /// The changes that turn this value into the other one, descending into records,
+    /// functions, sequences and variants. Sets are compared element-wise.
+    pub fn diff(&self, other: &TlaValue) -> Vec<ValueChange> {
+        let mut changes = Vec::new();
+        diff_at(String::new(), self, other, &mut changes);
+        changes
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::Path;
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::{ResolvedStatePair, TlaConstantAssignment, UpdateTrace};
+
+/// The part of an `UpdateTrace` that can be stored, so that the trace can be checked
+/// offline, e.g., with `tla-check`
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct RecordedTrace {
+    /// The name of the update method, e.g., `transfer`
+    pub update_name: String,
+    pub process_id: String,
+    pub canister_name: String,
+    pub state_pairs: Vec<ResolvedStatePair>,
+    pub constants: TlaConstantAssignment,
+}
+
+impl RecordedTrace {
+    pub fn new(update_name: &str, trace: &UpdateTrace) -> Self {
+        Self {
+            update_name: update_name.to_string(),
+            process_id: trace.update.process_id.clone(),
+            canister_name: trace.update.canister_name.clone(),
+            state_pairs: trace.state_pairs.clone(),
+            constants: trace.constants.clone(),
+        }
+    }
+}
+
+/// Writes the traces to a trace file, in the Candid encoding
+pub fn write_trace_file(path: &Path, traces: &[RecordedTrace]) -> Result<(), String> {
+    let bytes = candid::encode_one(traces)
+        .map_err(|e| format!("Can't encode the traces for {}: {}", path.display(), e))?;
+    fs::write(path, bytes).map_err(|e| format!("Can't write {}: {}", path.display(), e))
+}
+
+pub fn read_trace_file(path: &Path) -> Result<Vec<RecordedTrace>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    candid::decode_one(&bytes)
+        .map_err(|e| format!("{} is not a valid trace file: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_dir;
+    use crate::{GlobalState, TlaValue, ToTla};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn round_trips_traces() {
+        let mut start = GlobalState::new();
+        start.add(
+            "balances",
+            BTreeMap::from([("alice".to_string(), 10_u64)]).to_tla_value(),
+        );
+        start.add(
+            "pc",
+            TlaValue::Variant {
+                tag: "Start".to_string(),
+                value: Box::new(true.to_tla_value()),
+            },
+        );
+        let mut end = start.clone();
+        end.add("pc", "Done".to_tla_value());
+        let trace = RecordedTrace {
+            update_name: "transfer".to_string(),
+            process_id: "p1".to_string(),
+            canister_name: "ledger".to_string(),
+            state_pairs: vec![ResolvedStatePair { start, end }],
+            constants: TlaConstantAssignment {
+                constants: BTreeMap::from([("MAX".to_string(), 3_u64.to_tla_value())]),
+            },
+        };
+        let dir = test_dir("trace_file");
+        let path = dir.join("traces");
+        write_trace_file(&path, &[trace.clone(), trace.clone()]).unwrap();
+        let read = read_trace_file(&path);
+        fs::write(&path, "garbage").unwrap();
+        let garbage = read_trace_file(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read.unwrap(), vec![trace.clone(), trace]);
+        assert!(garbage.unwrap_err().contains("is not a valid trace file"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{GlobalState, ResolvedStatePair, UpdateTrace};
+
+/// Describes which parts of a state pair are irrelevant when deciding whether two
+/// pairs have to be checked separately.
+#[derive(Clone, Debug, Default)]
+pub struct Canonicalization {
+    pub ignored_variables: BTreeSet<String>,
+}
+
+impl Canonicalization {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ignore(mut self, variable: &str) -> Self {
+        self.ignored_variables.insert(variable.to_string());
+        self
+    }
+
+    fn canonicalize_state(&self, state: &GlobalState) -> GlobalState {
+        let mut canonical = state.clone();
+        canonical
+            .0
+             .0
+            .retain(|name, _| !self.ignored_variables.contains(name));
+        canonical
+    }
+
+    pub fn canonicalize(&self, pair: &ResolvedStatePair) -> ResolvedStatePair {
+        ResolvedStatePair {
+            start: self.canonicalize_state(&pair.start),
+            end: self.canonicalize_state(&pair.end),
+        }
+    }
+}
+
+impl ResolvedStatePair {
+    /// A step is stuttering if it doesn't change any variable
+    pub fn is_stuttering(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// The state pairs of a trace that still need to be checked, together with a record
+/// of which of the recorded pairs were dropped and why.
+#[derive(Clone, Debug)]
+pub struct NormalizedTrace {
+    pub state_pairs: Vec<ResolvedStatePair>,
+    /// For each pair in `state_pairs`, its index in the recorded trace
+    pub original_indices: Vec<usize>,
+    /// Indices of the recorded pairs that were dropped as stuttering steps
+    pub stuttering: Vec<usize>,
+    /// Maps the index of each dropped duplicate to the index of the pair that is kept instead
+    pub duplicates: BTreeMap<usize, usize>,
+}
+
+impl NormalizedTrace {
+    /// The number of model checker runs that normalization saves
+    pub fn checks_saved(&self) -> usize {
+        self.stuttering.len() + self.duplicates.len()
+    }
+}
+
+impl UpdateTrace {
+    /// Returns the indices of the stuttering steps in the recorded trace
+    pub fn stuttering_steps(&self) -> Vec<usize> {
+        self.state_pairs
+            .iter()
+            .enumerate()
+            .filter(|(_, pair)| pair.is_stuttering())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Drops the stuttering steps and all but the first occurrence of each pair. Steps
+    /// are stuttering if the recorded states are equal, while duplicates are found by
+    /// comparing the pairs after the given canonicalization. The kept pairs are returned
+    /// as recorded; the trace itself is left untouched.
+    pub fn normalize(&self, canonicalization: &Canonicalization) -> NormalizedTrace {
+        let mut normalized = NormalizedTrace {
+            state_pairs: Vec::new(),
+            original_indices: Vec::new(),
+            stuttering: Vec::new(),
+            duplicates: BTreeMap::new(),
+        };
+        let mut first_occurrences: BTreeMap<ResolvedStatePair, usize> = BTreeMap::new();
+        for (i, pair) in self.state_pairs.iter().enumerate() {
+            let canonical = canonicalization.canonicalize(pair);
+            if pair.is_stuttering() {
+                normalized.stuttering.push(i);
+            } else if let Some(first) = first_occurrences.get(&canonical) {
+                normalized.duplicates.insert(i, *first);
+            } else {
+                first_occurrences.insert(canonical, i);
+                normalized.state_pairs.push(pair.clone());
+                normalized.original_indices.push(i);
+            }
+        }
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::update;
+    use crate::ToTla;
+
+    fn state(counter: u64, timestamp: u64) -> GlobalState {
+        let mut state = GlobalState::new();
+        state.add("counter", counter.to_tla_value());
+        state.add("timestamp", timestamp.to_tla_value());
+        state
+    }
+
+    fn pair(start: GlobalState, end: GlobalState) -> ResolvedStatePair {
+        ResolvedStatePair { start, end }
+    }
+
+    fn trace(state_pairs: Vec<ResolvedStatePair>) -> UpdateTrace {
+        crate::test_helpers::trace(update("can", "pid"), state_pairs)
+    }
+
+    #[test]
+    fn drops_stuttering_and_duplicate_pairs() {
+        let trace = trace(vec![
+            pair(state(0, 1), state(1, 1)),
+            pair(state(1, 1), state(1, 1)),
+            pair(state(0, 1), state(1, 1)),
+            pair(state(1, 1), state(2, 1)),
+        ]);
+        assert_eq!(trace.stuttering_steps(), vec![1]);
+        let normalized = trace.normalize(&Canonicalization::new());
+        assert_eq!(normalized.original_indices, vec![0, 3]);
+        assert_eq!(normalized.stuttering, vec![1]);
+        assert_eq!(normalized.duplicates, BTreeMap::from([(2, 0)]));
+        assert_eq!(normalized.checks_saved(), 2);
+        assert_eq!(trace.state_pairs.len(), 4);
+    }
+
+    #[test]
+    fn ignored_variables_are_not_compared() {
+        let trace = trace(vec![
+            pair(state(0, 1), state(1, 2)),
+            pair(state(0, 3), state(1, 4)),
+            pair(state(1, 4), state(1, 5)),
+            pair(state(1, 5), state(1, 5)),
+        ]);
+        let normalized = trace.normalize(&Canonicalization::new().ignore("timestamp"));
+        // Changing only an ignored variable is still a step that has to be checked
+        assert_eq!(normalized.original_indices, vec![0, 2]);
+        assert_eq!(normalized.stuttering, vec![3]);
+        assert_eq!(normalized.duplicates, BTreeMap::from([(1, 0)]));
+        // The kept pairs are not canonicalized
+        assert_eq!(
+            normalized.state_pairs[0].end.get("timestamp"),
+            Some(&2_u64.to_tla_value())
+        );
+    }
+}
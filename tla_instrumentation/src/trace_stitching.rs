@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::{GlobalState, ResolvedStatePair, TlaConstantAssignment, TlaValue, UpdateTrace};
+
+/// A state pair of the stitched, system-wide trace, with a reference to the recorded
+/// pair it was derived from
+#[derive(Clone, Debug)]
+pub struct StitchedStatePair {
+    pub canister_name: String,
+    pub process_id: String,
+    /// Index of the trace (in the slice passed to `stitch_traces`) that recorded the pair
+    pub trace_index: usize,
+    /// Index of the pair within the recorded trace
+    pub pair_index: usize,
+    pub pair: ResolvedStatePair,
+}
+
+#[derive(Clone, Debug)]
+pub struct StitchedTrace {
+    pub state_pairs: Vec<StitchedStatePair>,
+    pub constants: TlaConstantAssignment,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StitchError {
+    /// A canister with recorded traces received more requests than there are traces for it
+    MissingCallee { caller: String, callee: String },
+    /// Two traces assign different values to the same constant
+    ConflictingConstant {
+        name: String,
+        first: TlaValue,
+        second: TlaValue,
+    },
+}
+
+impl Display for StitchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StitchError::MissingCallee { caller, callee } => write!(
+                f,
+                "Canister {} sent a request to {}, but there is no unconsumed trace of {} left",
+                caller, callee, callee
+            ),
+            StitchError::ConflictingConstant {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "The traces assign different values to the constant {}: {} and {}",
+                name, first, second
+            ),
+        }
+    }
+}
+
+/// Overlays the values recorded by one canister on top of the system-wide state.
+/// Function-valued variables (such as locals, which map process IDs to values) are
+/// updated pointwise, so that the entries of other processes are preserved.
+fn overlay(system: &GlobalState, recorded: &GlobalState) -> GlobalState {
+    let mut result = system.clone();
+    for (name, value) in &recorded.0 .0 {
+        let merged = match (system.get(name), value) {
+            (Some(TlaValue::Function(old)), TlaValue::Function(new)) => {
+                let mut merged = old.clone();
+                merged.extend(new.clone());
+                TlaValue::Function(merged)
+            }
+            _ => value.clone(),
+        };
+        result.add(name, merged);
+    }
+    result
+}
+
+fn new_requests(pair: &ResolvedStatePair, buffer: &str) -> Vec<TlaValue> {
+    let sent = match pair.end.get(buffer) {
+        Some(TlaValue::Seq(requests)) => requests,
+        _ => return Vec::new(),
+    };
+    let pending = match pair.start.get(buffer) {
+        Some(TlaValue::Seq(requests)) => requests.clone(),
+        _ => Vec::new(),
+    };
+    sent.iter()
+        .filter(|r| !pending.contains(r))
+        .cloned()
+        .collect()
+}
+
+fn remove_request(state: &mut GlobalState, buffer: &str, request: &TlaValue) {
+    if let Some(TlaValue::Seq(requests)) = state.get(buffer) {
+        let mut requests = requests.clone();
+        if let Some(pos) = requests.iter().position(|r| r == request) {
+            requests.remove(pos);
+        }
+        state.add(buffer, TlaValue::Seq(requests));
+    }
+}
+
+fn add_responses(state: &mut GlobalState, buffer: &str, responses: &TlaValue) {
+    let merged = match (state.get(buffer), responses) {
+        (Some(TlaValue::Set(old)), TlaValue::Set(new)) => {
+            TlaValue::Set(old.union(new).cloned().collect())
+        }
+        _ => responses.clone(),
+    };
+    state.add(buffer, merged);
+}
+
+/// A request linking a caller's state pair to the trace of the callee that handles it
+struct Call {
+    request_buffer: String,
+    request: TlaValue,
+    response_buffer: String,
+    response: Option<TlaValue>,
+}
+
+struct Stitcher<'a> {
+    traces: &'a [UpdateTrace],
+    canisters: BTreeSet<String>,
+    callees: BTreeMap<String, VecDeque<usize>>,
+    system: GlobalState,
+    state_pairs: Vec<StitchedStatePair>,
+}
+
+impl<'a> Stitcher<'a> {
+    fn stitch(&mut self, trace_index: usize, call: Option<Call>) -> Result<(), StitchError> {
+        let trace = &self.traces[trace_index];
+        let canister = trace.update.canister_name.clone();
+        let last = trace.state_pairs.len().saturating_sub(1);
+        for (pair_index, pair) in trace.state_pairs.iter().enumerate() {
+            let start = overlay(&self.system, &pair.start);
+            let mut end = overlay(&start, &pair.end);
+            if let Some(call) = &call {
+                if pair_index == 0 {
+                    remove_request(&mut end, &call.request_buffer, &call.request);
+                }
+                if let (true, Some(response)) = (pair_index == last, &call.response) {
+                    add_responses(&mut end, &call.response_buffer, response);
+                }
+            }
+            self.system = end.clone();
+            self.state_pairs.push(StitchedStatePair {
+                canister_name: canister.clone(),
+                process_id: trace.update.process_id.clone(),
+                trace_index,
+                pair_index,
+                pair: ResolvedStatePair { start, end },
+            });
+
+            let destinations: Vec<String> = self.canisters.iter().cloned().collect();
+            for destination in destinations {
+                let request_buffer = format!("{}_to_{}", canister, destination);
+                for request in new_requests(pair, &request_buffer) {
+                    let callee = self
+                        .callees
+                        .get_mut(&destination)
+                        .and_then(|queue| queue.pop_front())
+                        .ok_or_else(|| StitchError::MissingCallee {
+                            caller: canister.clone(),
+                            callee: destination.clone(),
+                        })?;
+                    let response_buffer = format!("{}_to_{}", destination, canister);
+                    let response = trace
+                        .state_pairs
+                        .get(pair_index + 1)
+                        .and_then(|next| next.start.get(&response_buffer))
+                        .cloned();
+                    self.stitch(
+                        callee,
+                        Some(Call {
+                            request_buffer: request_buffer.clone(),
+                            request,
+                            response_buffer,
+                            response,
+                        }),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn count_requests(traces: &[UpdateTrace], canisters: &BTreeSet<String>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for trace in traces {
+        for destination in canisters {
+            let buffer = format!("{}_to_{}", trace.update.canister_name, destination);
+            let sent: usize = trace
+                .state_pairs
+                .iter()
+                .map(|pair| new_requests(pair, &buffer).len())
+                .sum();
+            *counts.entry(destination.clone()).or_insert(0) += sent;
+        }
+    }
+    counts
+}
+
+/// Combines the traces of several instrumented canisters into a single trace over the
+/// system-wide state.
+///
+/// Every request that one canister puts into its `<caller>_to_<callee>` buffer is
+/// linked to a trace of the callee: the k-th request sent to a canister is handled by
+/// the k-th trace of that canister in `traces`. The callee's state pairs are spliced in
+/// right after the caller's pair that sent the request; the callee's first step consumes
+/// the request, and its last step produces the response that the caller's next pair
+/// receives. Traces of canisters that weren't called are treated as top-level updates
+/// and stitched in the given order. Requests to canisters without any recorded trace
+/// are left untouched.
+///
+/// Each stitched state contains the latest known value of every variable of every
+/// canister, so the resulting pairs can be checked against a system-level spec.
+pub fn stitch_traces(traces: &[UpdateTrace]) -> Result<StitchedTrace, StitchError> {
+    let canisters: BTreeSet<String> = traces
+        .iter()
+        .map(|t| t.update.canister_name.clone())
+        .collect();
+    let request_counts = count_requests(traces, &canisters);
+    let mut callees: BTreeMap<String, VecDeque<usize>> = BTreeMap::new();
+    let mut roots = Vec::new();
+    for (i, trace) in traces.iter().enumerate() {
+        let canister = &trace.update.canister_name;
+        let queue = callees.entry(canister.clone()).or_default();
+        if queue.len() < request_counts.get(canister).cloned().unwrap_or(0) {
+            queue.push_back(i);
+        } else {
+            roots.push(i);
+        }
+    }
+
+    let mut constants: BTreeMap<String, TlaValue> = BTreeMap::new();
+    for trace in traces {
+        for (name, value) in &trace.constants.constants {
+            match constants.get(name) {
+                Some(existing) if existing != value => {
+                    return Err(StitchError::ConflictingConstant {
+                        name: name.clone(),
+                        first: existing.clone(),
+                        second: value.clone(),
+                    })
+                }
+                _ => {
+                    constants.insert(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    let mut stitcher = Stitcher {
+        traces,
+        canisters,
+        callees,
+        system: GlobalState::new(),
+        state_pairs: Vec::new(),
+    };
+    for root in roots {
+        stitcher.stitch(root, None)?;
+    }
+    Ok(StitchedTrace {
+        state_pairs: stitcher.state_pairs,
+        constants: TlaConstantAssignment { constants },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::update;
+    use crate::ToTla;
+
+    fn state(vars: Vec<(&str, TlaValue)>) -> GlobalState {
+        let mut state = GlobalState::new();
+        for (name, value) in vars {
+            state.add(name, value);
+        }
+        state
+    }
+
+    fn pc(pid: &str, label: &str) -> TlaValue {
+        BTreeMap::from([(pid, label)]).to_tla_value()
+    }
+
+    #[test]
+    fn splices_callee_between_request_and_response() {
+        let request = TlaValue::Record(BTreeMap::from([
+            ("caller".to_string(), "A_PID".to_tla_value()),
+            (
+                "method_and_args".to_string(),
+                TlaValue::Variant {
+                    tag: "transfer".to_string(),
+                    value: Box::new(5_u64.to_tla_value()),
+                },
+            ),
+        ]));
+        let response = BTreeSet::from([TlaValue::Record(BTreeMap::from([
+            ("caller".to_string(), "A_PID".to_tla_value()),
+            ("response".to_string(), true.to_tla_value()),
+        ]))])
+        .to_tla_value();
+        let no_requests = Vec::<TlaValue>::new().to_tla_value();
+        let no_responses = BTreeSet::<TlaValue>::new().to_tla_value();
+
+        let caller = UpdateTrace {
+            update: update("a", "A_PID"),
+            state_pairs: vec![
+                ResolvedStatePair {
+                    start: state(vec![
+                        ("counter", 0_u64.to_tla_value()),
+                        ("pc", pc("A_PID", "Start")),
+                        ("a_to_b", no_requests.clone()),
+                        ("b_to_a", no_responses.clone()),
+                    ]),
+                    end: state(vec![
+                        ("counter", 1_u64.to_tla_value()),
+                        ("pc", pc("A_PID", "Wait")),
+                        ("a_to_b", vec![request.clone()].to_tla_value()),
+                        ("b_to_a", no_responses.clone()),
+                    ]),
+                },
+                ResolvedStatePair {
+                    start: state(vec![
+                        ("counter", 1_u64.to_tla_value()),
+                        ("pc", pc("A_PID", "Wait")),
+                        ("a_to_b", no_requests.clone()),
+                        ("b_to_a", response.clone()),
+                    ]),
+                    end: state(vec![
+                        ("counter", 2_u64.to_tla_value()),
+                        ("pc", pc("A_PID", "End")),
+                        ("a_to_b", no_requests.clone()),
+                        ("b_to_a", no_responses.clone()),
+                    ]),
+                },
+            ],
+            constants: TlaConstantAssignment {
+                constants: BTreeMap::from([("MAX".to_string(), 2_u64.to_tla_value())]),
+            },
+        };
+        let callee = UpdateTrace {
+            update: update("b", "B_PID"),
+            state_pairs: vec![ResolvedStatePair {
+                start: state(vec![
+                    ("balance", 10_u64.to_tla_value()),
+                    ("pc", pc("B_PID", "Start")),
+                ]),
+                end: state(vec![
+                    ("balance", 5_u64.to_tla_value()),
+                    ("pc", pc("B_PID", "End")),
+                ]),
+            }],
+            constants: TlaConstantAssignment {
+                constants: BTreeMap::from([("MAX".to_string(), 2_u64.to_tla_value())]),
+            },
+        };
+
+        // The callee finishes first, so its trace is recorded first
+        let stitched = stitch_traces(&[callee, caller]).expect("Stitching failed");
+        let order: Vec<_> = stitched
+            .state_pairs
+            .iter()
+            .map(|p| (p.canister_name.as_str(), p.pair_index))
+            .collect();
+        assert_eq!(order, vec![("a", 0), ("b", 0), ("a", 1)]);
+        assert_eq!(
+            stitched.constants.constants.get("MAX"),
+            Some(&2_u64.to_tla_value())
+        );
+
+        let callee_pair = &stitched.state_pairs[1].pair;
+        assert_eq!(
+            callee_pair.start.get("a_to_b"),
+            Some(&vec![request].to_tla_value())
+        );
+        assert_eq!(callee_pair.end.get("a_to_b"), Some(&no_requests));
+        assert_eq!(callee_pair.end.get("b_to_a"), Some(&response));
+        assert_eq!(
+            callee_pair.start.get("counter"),
+            Some(&1_u64.to_tla_value())
+        );
+        assert_eq!(
+            callee_pair.end.get("pc"),
+            Some(&BTreeMap::from([("A_PID", "Wait"), ("B_PID", "End")]).to_tla_value())
+        );
+
+        let last = &stitched.state_pairs[2].pair;
+        assert_eq!(last.start.get("balance"), Some(&5_u64.to_tla_value()));
+        assert_eq!(last.start, stitched.state_pairs[1].pair.end);
+    }
+
+    #[test]
+    fn conflicting_constants_are_rejected() {
+        let trace = |value: u64| UpdateTrace {
+            update: update("a", "A_PID"),
+            state_pairs: vec![],
+            constants: TlaConstantAssignment {
+                constants: BTreeMap::from([("MAX".to_string(), value.to_tla_value())]),
+            },
+        };
+        assert!(matches!(
+            stitch_traces(&[trace(1), trace(2)]),
+            Err(StitchError::ConflictingConstant { .. })
+        ));
+    }
+}
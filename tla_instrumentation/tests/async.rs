@@ -15,9 +15,7 @@ fn init_global() {
 #[macro_use]
 mod tla_stuff {
     use super::{CAN_NAME, PID};
-    use tla_instrumentation::{
-        GlobalState, Label, MessageHandlerState, ResolvedStatePair, ToTla, Update, VarAssignment,
-    };
+    use tla_instrumentation::{GlobalState, MessageHandlerState, ResolvedStatePair, ToTla, Update};
 
     static mut STATE_PAIRS: Vec<ResolvedStatePair> = Vec::new();
 
@@ -78,14 +76,7 @@ mod tla_stuff {
     }
 
     pub fn my_f_desc() -> Update {
-        Update {
-            default_start_locals: VarAssignment::new(),
-            default_end_locals: VarAssignment::new(),
-            start_label: Label::new("Start_Label"),
-            end_label: Label::new("End_Label"),
-            process_id: PID.to_string(),
-            canister_name: CAN_NAME.to_string(),
-        }
+        Update::new("Start_Label", "End_Label", PID, CAN_NAME)
     }
 }
 
@@ -0,0 +1,187 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ptr::addr_of_mut,
+};
+
+use tla_instrumentation::{
+    tla_call,
+    tla_value::{TlaValue, ToTla},
+    CallOutcome, Destination, InstrumentationState, ToCallOutcome,
+};
+use tla_instrumentation_proc_macros::tla_update_method;
+
+#[macro_use]
+mod tla_stuff {
+    use crate::CallingCanister;
+
+    pub const PID: &str = "Caller_PID";
+    pub const CAN_NAME: &str = "caller";
+
+    use local_key::task_local;
+    use std::sync::RwLock;
+    use tla_instrumentation::{
+        GlobalState, InstrumentationState, OutcomeTags, ResponseEncoding, ToTla, Update,
+        UpdateTrace,
+    };
+
+    task_local! {
+        pub static TLA_INSTRUMENTATION_STATE: InstrumentationState;
+    }
+
+    pub static TLA_TRACES: RwLock<Vec<UpdateTrace>> = RwLock::new(Vec::new());
+
+    pub fn tla_get_globals(c: &CallingCanister) -> GlobalState {
+        let mut state = GlobalState::new();
+        state.add("attempts", c.attempts.to_tla_value());
+        state
+    }
+
+    macro_rules! tla_get_globals {
+        ($self:expr) => {
+            tla_stuff::tla_get_globals($self)
+        };
+    }
+
+    pub fn transfer_desc() -> Update {
+        Update::new("Start_Transfer", "Done", PID, CAN_NAME)
+            .with_response_encoding(ResponseEncoding::Variant(OutcomeTags::default()))
+    }
+}
+
+use tla_stuff::{transfer_desc, CAN_NAME, PID, TLA_INSTRUMENTATION_STATE, TLA_TRACES};
+
+struct CallingCanister {
+    pub attempts: u64,
+}
+
+static mut CANISTER: CallingCanister = CallingCanister { attempts: 0 };
+
+async fn ledger_transfer(amount: u64) -> Result<u64, (u32, String)> {
+    if amount > 5 {
+        Err((4, "insufficient funds".to_string()))
+    } else {
+        Ok(amount)
+    }
+}
+
+/// Fails without sending the request, e.g., as the output queue is full
+async fn full_queue_transfer(_amount: u64) -> Result<u64, (u32, String)> {
+    Err((2, "output queue full".to_string()))
+}
+
+/// Error code 2 stands for the call failing synchronously
+fn transfer_outcome(result: &Result<u64, (u32, String)>) -> CallOutcome {
+    match result {
+        Err((2, message)) => CallOutcome::SyncFailure {
+            message: message.clone(),
+        },
+        other => other.to_call_outcome(),
+    }
+}
+
+impl CallingCanister {
+    #[tla_update_method(transfer_desc())]
+    pub async fn transfer(&mut self, amount: u64) -> Result<u64, (u32, String)> {
+        self.attempts += 1;
+        tla_call!(
+            "WaitForLedger",
+            Destination::new("ledger"),
+            "transfer",
+            amount,
+            ledger_transfer(amount)
+        )
+    }
+
+    #[tla_update_method(transfer_desc())]
+    pub async fn transfer_with_full_queue(&mut self, amount: u64) -> Result<u64, (u32, String)> {
+        self.attempts += 1;
+        tla_call!(
+            "WaitForLedger",
+            Destination::new("ledger"),
+            "transfer",
+            amount,
+            full_queue_transfer(amount),
+            outcome = transfer_outcome
+        )
+    }
+}
+
+#[test]
+fn call_test() {
+    let result = unsafe {
+        let canister = &mut *addr_of_mut!(CANISTER);
+        tokio_test::block_on(canister.transfer(7))
+    };
+    assert_eq!(result, Err((4, "insufficient funds".to_string())));
+
+    let traces = TLA_TRACES.read().unwrap();
+    let pairs = &traces[0].state_pairs;
+    assert_eq!(pairs.len(), 2);
+
+    let outgoing = format!("{}_to_{}", CAN_NAME, "ledger");
+    let incoming = format!("{}_to_{}", "ledger", CAN_NAME);
+    assert_eq!(
+        pairs[0].end.get(&outgoing),
+        Some(
+            &vec![TlaValue::Record(BTreeMap::from([
+                ("caller".to_string(), PID.to_tla_value()),
+                (
+                    "method_and_args".to_string(),
+                    TlaValue::Variant {
+                        tag: "transfer".to_string(),
+                        value: Box::new(7_u64.to_tla_value())
+                    }
+                )
+            ]))]
+            .to_tla_value()
+        )
+    );
+    assert_eq!(
+        pairs[1].start.get(&incoming),
+        Some(
+            &BTreeSet::from([TlaValue::Record(BTreeMap::from([
+                ("caller".to_string(), PID.to_tla_value()),
+                (
+                    "response".to_string(),
+                    TlaValue::Variant {
+                        tag: "Reject".to_string(),
+                        value: Box::new(TlaValue::Record(BTreeMap::from([
+                            ("code".to_string(), "4".to_tla_value()),
+                            ("message".to_string(), "insufficient funds".to_tla_value()),
+                        ])))
+                    }
+                )
+            ]))])
+            .to_tla_value()
+        )
+    );
+    drop(traces);
+
+    // The same update, but the call fails synchronously
+    let result = unsafe {
+        let canister = &mut *addr_of_mut!(CANISTER);
+        tokio_test::block_on(canister.transfer_with_full_queue(1))
+    };
+    assert_eq!(result, Err((2, "output queue full".to_string())));
+    assert_eq!(
+        TLA_TRACES.read().unwrap()[1].state_pairs[1]
+            .start
+            .get(&incoming),
+        Some(
+            &BTreeSet::from([TlaValue::Record(BTreeMap::from([
+                ("caller".to_string(), PID.to_tla_value()),
+                (
+                    "response".to_string(),
+                    TlaValue::Variant {
+                        tag: "SyncFailure".to_string(),
+                        value: Box::new(TlaValue::Record(BTreeMap::from([(
+                            "message".to_string(),
+                            "output queue full".to_tla_value()
+                        )])))
+                    }
+                )
+            ]))])
+            .to_tla_value()
+        )
+    );
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, ptr::addr_of_mut};
+
+use tla_instrumentation::{
+    tla_log_label, tla_log_locals, tla_value::ToTla, InstrumentationState, TlaValue,
+};
+use tla_instrumentation_proc_macros::tla_update_method;
+
+#[macro_use]
+mod tla_stuff {
+    use crate::LabelCanister;
+
+    pub const PID: &str = "Label_PID";
+    pub const CAN_NAME: &str = "labelcan";
+
+    use local_key::task_local;
+    use std::{collections::BTreeMap, sync::RwLock};
+    use tla_instrumentation::{
+        GlobalState, InstrumentationState, Label, ResponseEncoding, TlaConstantAssignment, ToTla,
+        Update, UpdateTrace, VarAssignment,
+    };
+
+    task_local! {
+        pub static TLA_INSTRUMENTATION_STATE: InstrumentationState;
+    }
+
+    pub static TLA_TRACES: RwLock<Vec<UpdateTrace>> = RwLock::new(Vec::new());
+
+    pub fn tla_get_globals(c: &LabelCanister) -> GlobalState {
+        let mut state = GlobalState::new();
+        state.add("balance", c.balance.to_tla_value());
+        state.add("committed", c.committed.to_tla_value());
+        state
+    }
+
+    macro_rules! tla_get_globals {
+        ($self:expr) => {
+            tla_stuff::tla_get_globals($self)
+        };
+    }
+
+    pub fn withdraw_desc() -> Update {
+        Update {
+            default_start_locals: VarAssignment::new(),
+            default_end_locals: VarAssignment::new(),
+            start_label: Label::new("Start_Withdraw"),
+            end_label: Label::new("Done"),
+            process_id: PID.to_string(),
+            canister_name: CAN_NAME.to_string(),
+            post_process: |_| TlaConstantAssignment {
+                constants: BTreeMap::new(),
+            },
+            response_encoding: ResponseEncoding::Plain,
+        }
+    }
+}
+
+use tla_stuff::{withdraw_desc, PID, TLA_INSTRUMENTATION_STATE, TLA_TRACES};
+
+struct LabelCanister {
+    pub balance: u64,
+    pub committed: bool,
+}
+
+static mut CANISTER: LabelCanister = LabelCanister {
+    balance: 10,
+    committed: false,
+};
+
+impl LabelCanister {
+    #[tla_update_method(withdraw_desc())]
+    pub async fn withdraw(&mut self, amount: u64) -> () {
+        self.balance -= amount;
+        let withdrawn = amount;
+        tla_log_locals! {withdrawn: withdrawn};
+        tla_log_label!("Commit");
+        self.committed = true;
+    }
+}
+
+#[test]
+fn label_test() {
+    unsafe {
+        let canister = &mut *addr_of_mut!(CANISTER);
+        tokio_test::block_on(canister.withdraw(3));
+    }
+    let trace = &TLA_TRACES.read().unwrap()[0];
+    let pairs = &trace.state_pairs;
+    assert_eq!(pairs.len(), 2);
+    let pc = |label: &str| BTreeMap::from([(PID, label)]).to_tla_value();
+
+    let first = &pairs[0];
+    assert_eq!(first.start.get("pc"), Some(&pc("Start_Withdraw")));
+    assert_eq!(first.end.get("pc"), Some(&pc("Commit")));
+    assert_eq!(first.start.get("balance"), Some(&10_u64.to_tla_value()));
+    assert_eq!(first.end.get("balance"), Some(&7_u64.to_tla_value()));
+    assert_eq!(first.end.get("committed"), Some(&false.to_tla_value()));
+    assert_eq!(
+        first.end.get("withdrawn"),
+        Some(&BTreeMap::from([(PID, 3_u64)]).to_tla_value())
+    );
+
+    let second = &pairs[1];
+    assert_eq!(second.start, first.end);
+    assert_eq!(second.end.get("pc"), Some(&pc("Done")));
+    assert_eq!(second.end.get("committed"), Some(&true.to_tla_value()));
+    assert_eq!(second.end.get("withdrawn"), None::<&TlaValue>);
+}
@@ -26,8 +26,8 @@ mod tla_stuff {
     use local_key::task_local;
     use std::{collections::BTreeMap, sync::RwLock};
     use tla_instrumentation::{
-        GlobalState, InstrumentationState, Label, TlaConstantAssignment, TlaValue, ToTla, Update,
-        UpdateTrace, VarAssignment,
+        GlobalState, InstrumentationState, Label, ResponseEncoding, TlaConstantAssignment,
+        TlaValue, ToTla, Update, UpdateTrace, VarAssignment,
     };
 
     task_local! {
@@ -96,6 +96,7 @@ mod tla_stuff {
                 }
                 TlaConstantAssignment { constants }
             },
+            response_encoding: ResponseEncoding::Plain,
         }
     }
 }